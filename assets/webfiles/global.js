@@ -31,3 +31,9 @@ try {
 } catch (e) {
 	var prefix = '/';
 }
+
+// Token that must be sent back in the `X-CSRF-Token` header of mutating requests
+function csrfToken() {
+	let match = document.cookie.match(/(?:^|;\s*)csrf=([^;]*)/);
+	return match ? match[1] : '';
+}
@@ -49,6 +49,7 @@ async function submit() {
 		credentials: 'same-origin',
 		headers: {
 			'Content-Type': 'application/json',
+			'X-CSRF-Token': csrfToken(),
 		},
 		redirect: 'follow',
 		referrerPolicy: 'no-referrer',
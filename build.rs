@@ -19,8 +19,34 @@
 //
 // Email: hex0x0000@protonmail.com
 
+use std::{env, fs, process::Command};
 use tcloud_assets_include::include;
 
+/// Exposes the compiler and `tcloud-library` versions, which plugin libraries must match
+fn plugin_abi() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=TCLOUD_RUSTC_VERSION={rustc_version}");
+
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let library_version = lock
+        .split("[[package]]")
+        .find(|package| package.contains("name = \"tcloud-library\""))
+        .and_then(|package| package.lines().find_map(|line| line.strip_prefix("version = \"")))
+        .map_or("unknown", |version| version.trim_end_matches('"'));
+    println!("cargo:rustc-env=TCLOUD_LIBRARY_VERSION={library_version}");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=assets");
+}
+
 fn main() {
     include("assets", vec!["ico", "256.png"], vec!["global.js"]);
+    plugin_abi();
 }
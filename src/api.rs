@@ -21,29 +21,50 @@
 
 #[macro_use]
 mod macros;
+pub mod admin;
+pub mod audit;
 pub mod auth;
+pub mod health;
 pub mod plugins;
 pub mod token;
-use crate::config;
-use actix_web::{get, HttpResponse, Responder};
+use crate::{auth::error::AuthError, config, database, plugins::Plugins};
+use actix_identity::Identity;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use async_sqlite::Pool;
 use std::sync::OnceLock;
-use tcloud_library::serde_json::json;
+use tcloud_library::{
+    error::ErrToResponse,
+    serde_json::{json, Value},
+};
 
-static INFO: OnceLock<String> = OnceLock::new();
+static INFO: OnceLock<Value> = OnceLock::new();
 
-/// Returns server info
+/// Returns server info and the plugins the user can use, see [`plugins::list`] for their status
 #[get("/info")]
-pub async fn info() -> impl Responder {
-    HttpResponse::Ok().content_type("application/json").body(
-        INFO.get_or_init(|| {
+pub async fn info(req: HttpRequest, pool: web::Data<Pool>, plugins: web::Data<Plugins>, user: Option<Identity>) -> impl Responder {
+    let user = match plugins::get_user(&pool, &req, user).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let mut info = INFO
+        .get_or_init(|| {
             json!({
                 "name": config!(server_name),
                 "version": env!("CARGO_PKG_VERSION"),
                 "description": config!(description),
                 "source": env!("CARGO_PKG_REPOSITORY")
             })
-            .to_string()
         })
-        .to_owned(),
-    )
+        .clone();
+    info["plugins"] = json!(plugins.info(&user));
+    HttpResponse::Ok().content_type("application/json").body(info.to_string())
+}
+
+/// Returns a response if the user is not an admin
+pub async fn check_admin(pool: &Pool, username: String) -> Result<(), HttpResponse> {
+    match database::auth::is_admin(pool, username).await {
+        Ok(Some(true)) => Ok(()),
+        Ok(_) => Err(HttpResponse::Forbidden().body("")),
+        Err(e) => Err(Into::<AuthError>::into(e).to_response()),
+    }
 }
@@ -0,0 +1,124 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::check_admin;
+use crate::audit::{self, Event};
+use crate::utils::get_ip;
+use crate::{database, logging, plugins};
+use actix_identity::error::GetIdentityError;
+use actix_identity::Identity;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use async_sqlite::Pool;
+use serde::Deserialize;
+use tcloud_library::error::ErrToResponse;
+use tcloud_library::serde_json::json;
+
+/// New log filter, e.g. `info,tiny_cloud::auth=debug,actix_web=warn`
+#[derive(Deserialize)]
+struct LogFilter {
+    filter: String,
+}
+
+#[derive(Deserialize)]
+struct PluginState {
+    name: String,
+    enabled: bool,
+}
+
+/// Returns the log filter currently applied
+#[get("/log-filter")]
+pub async fn get_log_filter(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    let username = get_user!(user.id());
+    if let Err(e) = check_admin(&pool, username).await {
+        return e;
+    }
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json!({ "filter": logging::get_filter() }).to_string())
+}
+
+/// Changes the log filter until the server restarts
+#[post("/log-filter")]
+pub async fn set_log_filter(req: HttpRequest, user: Identity, pool: web::Data<Pool>, filter: web::Json<LogFilter>) -> impl Responder {
+    let username = get_user!(user.id());
+    if let Err(e) = check_admin(&pool, username.clone()).await {
+        return e;
+    }
+    let filter = filter.into_inner().filter;
+    match logging::set_filter(&filter) {
+        Ok(()) => {
+            log::warn!("Log filter changed to `{}`", logging::get_filter());
+            audit::record(
+                &pool,
+                Event::AdminAction,
+                Some(&username),
+                Some(&get_ip(&req)),
+                Some(format!("log filter: {}", logging::get_filter())),
+            )
+            .await;
+            HttpResponse::Ok().body("")
+        }
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+/// Lists every plugin with its state and access policy
+#[get("/plugins")]
+pub async fn list_plugins(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    let username = get_user!(user.id());
+    if let Err(e) = check_admin(&pool, username).await {
+        return e;
+    }
+    let list: Vec<_> = plugins::list_all()
+        .into_iter()
+        .map(|name| json!({ "name": name, "enabled": plugins::is_enabled(&name), "access": plugins::access(&name) }))
+        .collect();
+    HttpResponse::Ok().content_type("application/json").body(json!(list).to_string())
+}
+
+/// Enables or disables a plugin until the server restarts
+#[post("/plugins")]
+pub async fn set_plugin(req: HttpRequest, user: Identity, pool: web::Data<Pool>, state: web::Json<PluginState>) -> impl Responder {
+    let username = get_user!(user.id());
+    if let Err(e) = check_admin(&pool, username.clone()).await {
+        return e;
+    }
+    let PluginState { name, enabled } = state.into_inner();
+    if !plugins::list_all().contains(&name) {
+        return HttpResponse::NotFound().body("");
+    }
+    if enabled {
+        if let Err(e) = database::create_plugin_dirs(&pool, &name).await {
+            return Into::<plugins::error::PluginError>::into(e).to_response();
+        }
+    }
+    plugins::set_enabled(&name, enabled);
+    log::warn!("Plugin '{name}' {}", if enabled { "enabled" } else { "disabled" });
+    audit::record(
+        &pool,
+        Event::AdminAction,
+        Some(&username),
+        Some(&get_ip(&req)),
+        Some(format!("plugin {name} enabled: {enabled}")),
+    )
+    .await;
+    HttpResponse::Ok().body("")
+}
@@ -0,0 +1,70 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::check_admin;
+use crate::audit::{self, Event};
+use crate::database::audit::Filter;
+use crate::utils::get_ip;
+use actix_identity::error::GetIdentityError;
+use actix_identity::Identity;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use async_sqlite::Pool;
+use serde::Deserialize;
+use tcloud_library::error::ErrToResponse;
+use tcloud_library::serde_json::Value;
+
+/// Default number of records returned by a search
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Search filters, times are UNIX timestamps in seconds
+#[derive(Deserialize)]
+struct Search {
+    user: Option<String>,
+    event: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: Option<u32>,
+}
+
+/// Searches the audit log. Admins only.
+#[get("/audit")]
+pub async fn search(req: HttpRequest, user: Identity, pool: web::Data<Pool>, search: web::Query<Search>) -> impl Responder {
+    let username = get_user!(user.id());
+    let pool = pool.into_inner();
+    if let Err(e) = check_admin(&pool, username.clone()).await {
+        return e;
+    }
+    let search = search.into_inner();
+    let filter = Filter {
+        user: search.user,
+        event: search.event,
+        since: search.since,
+        until: search.until,
+        limit: search.limit.unwrap_or(DEFAULT_LIMIT),
+    };
+    audit::record(&pool, Event::AuditQueried, Some(&username), Some(&get_ip(&req)), None).await;
+    match audit::search(&pool, filter).await {
+        Ok(records) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(Value::Array(records).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
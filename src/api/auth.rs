@@ -20,13 +20,15 @@
 // Email: hex0x0000@protonmail.com
 
 use crate::{
+    audit::{self, Event},
     auth::{self, error::AuthError},
-    config,
+    config, metrics,
+    plugins::events::{self, ServerEvent},
     utils::{get_ip, sanitize_user},
 };
 use actix_identity::error::GetIdentityError;
 use actix_identity::Identity;
-use actix_web::{dev::ConnectionInfo, get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use async_sqlite::Pool;
 use serde::Deserialize;
 use tcloud_library::error::ErrToResponse;
@@ -55,12 +57,7 @@ pub struct Register {
 /// Registers new user and starts a new session
 #[cfg(not(feature = "totp-auth"))]
 #[post("/register")]
-pub async fn register(
-    req: HttpRequest,
-    conn: ConnectionInfo,
-    credentials: web::Json<Register>,
-    pool: web::Data<Pool>,
-) -> impl Responder {
+pub async fn register(req: HttpRequest, credentials: web::Json<Register>, pool: web::Data<Pool>) -> impl Responder {
     if config!(registration).is_some() {
         let credentials = credentials.into_inner();
         let password = Zeroizing::new(credentials.password.into_bytes());
@@ -70,15 +67,26 @@ pub async fn register(
                 if let Err(err) = Identity::login(&req.extensions(), credentials.user.clone()) {
                     return AuthError::InternalError(format!("Failed to build identity during registration: {err}")).to_response();
                 }
-                log::warn!("client [{}] registered as `{}`", get_ip(&conn), sanitize_user(&credentials.user));
+                log::warn!("client [{}] registered as `{}`", get_ip(&req), sanitize_user(&credentials.user));
+                let ip = get_ip(&req);
+                audit::record(&pool, Event::TokenConsumed, Some(&credentials.user), Some(&ip), None).await;
+                audit::record(&pool, Event::Register, Some(&credentials.user), Some(&ip), None).await;
                 HttpResponse::Ok().body("")
             }
             Err(err) => {
                 log::warn!(
                     "client [{}] tried to register as `{}`",
-                    get_ip(&conn),
+                    get_ip(&req),
                     sanitize_user(&credentials.user)
                 );
+                audit::record(
+                    &pool,
+                    Event::RegisterFailed,
+                    Some(&sanitize_user(&credentials.user)),
+                    Some(&get_ip(&req)),
+                    Some(err.err_type().into()),
+                )
+                .await;
                 err.to_response()
             }
         }
@@ -91,12 +99,7 @@ pub async fn register(
 /// Returns the TOTP as a url or qr code depending on the request
 #[cfg(feature = "totp-auth")]
 #[post("/register")]
-pub async fn register(
-    req: HttpRequest,
-    conn: ConnectionInfo,
-    credentials: web::Json<Register>,
-    pool: web::Data<Pool>,
-) -> impl Responder {
+pub async fn register(req: HttpRequest, credentials: web::Json<Register>, pool: web::Data<Pool>) -> impl Responder {
     use tcloud_library::serde_json::json;
 
     if config!(registration).is_some() {
@@ -108,7 +111,10 @@ pub async fn register(
                 if let Err(err) = Identity::login(&req.extensions(), credentials.user.clone()) {
                     return AuthError::InternalError(format!("Failed to build identity during registration: {err}")).to_response();
                 }
-                log::warn!("client [{}] registered as `{}`", get_ip(&conn), sanitize_user(&credentials.user));
+                log::warn!("client [{}] registered as `{}`", get_ip(&req), sanitize_user(&credentials.user));
+                let ip = get_ip(&req);
+                audit::record(&pool, Event::TokenConsumed, Some(&credentials.user), Some(&ip), None).await;
+                audit::record(&pool, Event::Register, Some(&credentials.user), Some(&ip), None).await;
                 let mut resp = HttpResponse::Ok();
                 resp.content_type("application/json");
                 if credentials.totp_as_qr {
@@ -123,9 +129,17 @@ pub async fn register(
             Err(err) => {
                 log::warn!(
                     "client [{}] tried to register as `{}`",
-                    get_ip(&conn),
+                    get_ip(&req),
                     sanitize_user(&credentials.user)
                 );
+                audit::record(
+                    &pool,
+                    Event::RegisterFailed,
+                    Some(&sanitize_user(&credentials.user)),
+                    Some(&get_ip(&req)),
+                    Some(err.err_type().into()),
+                )
+                .await;
                 err.to_response()
             }
         }
@@ -136,40 +150,57 @@ pub async fn register(
 
 /// Logins and starts a new session
 #[post("/login")]
-pub async fn login(req: HttpRequest, conn: ConnectionInfo, login: web::Json<Login>, pool: web::Data<Pool>) -> impl Responder {
+pub async fn login(req: HttpRequest, login: web::Json<Login>, pool: web::Data<Pool>) -> impl Responder {
     let login = login.into_inner();
     let pool = pool.into_inner();
+    let attempted_user = sanitize_user(&login.user);
     match auth::check(&pool, login).await {
         Ok(user) => {
-            log::warn!("client [{}] logged in as `{}`", get_ip(&conn), sanitize_user(&user));
+            log::warn!("client [{}] logged in as `{}`", get_ip(&req), sanitize_user(&user));
+            metrics::login(Ok(()));
+            audit::record(&pool, Event::Login, Some(&user), Some(&get_ip(&req)), None).await;
+            events::emit(ServerEvent::Login { user: user.clone() });
             if let Err(err) = Identity::login(&req.extensions(), user) {
                 return AuthError::InternalError(format!("Failed to build identity during registration: {err}")).to_response();
             }
             HttpResponse::Ok().body("")
         }
         Err(err) => {
-            log::warn!("client [{}] failed to login", get_ip(&conn));
+            log::warn!("client [{}] failed to login", get_ip(&req));
+            metrics::login(Err(err.err_type()));
+            audit::record(
+                &pool,
+                Event::LoginFailed,
+                Some(&attempted_user),
+                Some(&get_ip(&req)),
+                Some(err.err_type().into()),
+            )
+            .await;
             err.to_response()
         }
     }
 }
 
 /// Logs out and ends current session
-#[get("/logout")]
+#[post("/logout")]
 pub async fn logout(user: Identity) -> impl Responder {
+    if let Ok(name) = user.id() {
+        events::emit(ServerEvent::Logout { user: name });
+    }
     user.logout();
     HttpResponse::Ok()
 }
 
 // Deletes an user's own account
-#[get("/delete")]
-pub async fn delete(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+#[post("/delete")]
+pub async fn delete(req: HttpRequest, user: Identity, pool: web::Data<Pool>) -> impl Responder {
     let username = get_user!(user.id());
     let pool = pool.into_inner();
     user.logout();
-    if let Err(err) = auth::delete_user(&pool, username).await {
+    if let Err(err) = auth::delete_user(&pool, username.clone()).await {
         err.to_response()
     } else {
+        audit::record(&pool, Event::AccountDeleted, Some(&username), Some(&get_ip(&req)), None).await;
         HttpResponse::Ok().body("")
     }
 }
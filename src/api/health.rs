@@ -0,0 +1,112 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{config, plugins::Plugins};
+use actix_web::{get, web, HttpResponse, Responder};
+use async_sqlite::Pool;
+use std::path::PathBuf;
+use tcloud_library::serde_json::{json, Map, Value};
+use tokio::fs;
+
+/// Logs the error of a failed check, which is not shown to the caller
+fn check(name: &str, result: Result<(), String>) -> bool {
+    if let Err(e) = &result {
+        log::warn!("Readiness check `{name}` failed: {e}");
+    }
+    result.is_ok()
+}
+
+async fn check_database(pool: &Pool) -> Result<(), String> {
+    pool.conn(|conn| conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)))
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Database query failed: {e}"))
+}
+
+async fn check_data_directory() -> Result<(), String> {
+    let mut path = PathBuf::from(config!(data_directory));
+    path.push(".ready");
+    fs::write(&path, b"")
+        .await
+        .map_err(|e| format!("Data directory is not writable: {e}"))?;
+    fs::remove_file(&path)
+        .await
+        .map_err(|e| format!("Failed to clean up data directory check: {e}"))
+}
+
+#[cfg(not(feature = "no-tls"))]
+async fn check_tls() -> Result<(), String> {
+    let Some(tls) = config!(tls).as_ref() else {
+        return Ok(());
+    };
+    if !config!(server.listeners).iter().any(|l| l.is_tls()) {
+        return Ok(());
+    }
+    web::block(move || crate::tls::check(tls))
+        .await
+        .map_err(|e| format!("Failed to run TLS check: {e}"))?
+}
+
+#[cfg(feature = "no-tls")]
+async fn check_tls() -> Result<(), String> {
+    Ok(())
+}
+
+/// Returns 200 as long as the process is running
+#[get("/health")]
+pub async fn health() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json!({ "status": "up" }).to_string())
+}
+
+/// Checks the database, the data directory, the TLS certificate and key and every plugin.
+/// Returns 503 if any of them is degraded. Only the status of each check is returned, failures are logged.
+#[get("/ready")]
+pub async fn ready(pool: web::Data<Pool>, plugins: web::Data<Plugins>) -> impl Responder {
+    let database = check("database", check_database(&pool).await);
+    let data_directory = check("data_directory", check_data_directory().await);
+    let tls = check("tls", check_tls().await);
+    let plugins: Map<String, Value> = plugins
+        .health()
+        .await
+        .into_iter()
+        .map(|(name, health)| (name.to_string(), json!(check(&format!("plugin {name}"), health))))
+        .collect();
+    let is_ready = database && data_directory && tls && plugins.values().all(|ok| ok.as_bool() == Some(true));
+    let mut resp = if is_ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    resp.content_type("application/json").body(
+        json!({
+            "status": if is_ready { "ready" } else { "degraded" },
+            "checks": {
+                "database": database,
+                "data_directory": data_directory,
+                "tls": tls,
+                "plugins": plugins,
+            },
+        })
+        .to_string(),
+    )
+}
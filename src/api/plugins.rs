@@ -19,15 +19,31 @@
 //
 // Email: hex0x0000@protonmail.com
 
-use actix_identity::Identity;
+use actix_files::NamedFile;
+use actix_identity::{Identity, IdentityExt};
 use actix_multipart::form::{json::Json as MpJson, tempfile::TempFile, MultipartForm};
-use actix_web::{post, web, Responder};
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error, get,
+    http::header::{HeaderName, CONTENT_DISPOSITION},
+    middleware::Next,
+    post, web, FromRequest, HttpRequest, HttpResponse, Responder,
+};
 use async_sqlite::Pool;
-use tcloud_library::{error::ErrToResponse, plugin::User, Json};
+use std::{
+    future::Future,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+};
+use tcloud_library::{error::ErrToResponse, plugin::User, serde_json::json, Json};
 
+#[cfg(not(feature = "no-tls"))]
+use crate::tls;
 use crate::{
+    audit::{self, Event},
     database,
-    plugins::{error::PluginError, Plugins},
+    plugins::{self, error::PluginError, kv::KvStore, services::ServiceCall, Plugins},
     utils,
 };
 
@@ -48,35 +64,212 @@ async fn is_admin(pool: &Pool, username: &str) -> Result<bool, PluginError> {
     }
 }
 
+/// Gets the user from its session or, if there is none, from its client certificate
+pub async fn get_user(pool: &Pool, req: &HttpRequest, user: Option<Identity>) -> Result<Option<User>, HttpResponse> {
+    let name = match user {
+        Some(user) => user.id().map_err(utils::id_err_into)?,
+        #[cfg(not(feature = "no-tls"))]
+        None => match tls::cert_user(req) {
+            Some(name) => {
+                log::debug!("Client certificate authenticated as `{}`", utils::sanitize_user(name));
+                name.clone()
+            }
+            None => return Ok(None),
+        },
+        #[cfg(feature = "no-tls")]
+        None => return Ok(None),
+    };
+    let is_admin = is_admin(pool, &name).await.map_err(|e| e.to_response())?;
+    Ok(Some(User { name, is_admin }))
+}
+
+/// Set by a plugin on its response to make the server send the file at this path,
+/// relative to the plugin's data directory, instead of the response's body
+pub const X_TCLOUD_FILE: HeaderName = HeaderName::from_static("x-tcloud-file");
+
+/// Resolves `relative` inside `base`, refusing any path that leaves it, even through symlinks
+async fn confine(base: &Path, relative: &str) -> Result<PathBuf, PluginError> {
+    let relative = Path::new(relative);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(PluginError::InvalidPath);
+    }
+    let base = tokio::fs::canonicalize(base)
+        .await
+        .map_err(|e| PluginError::InternalError(format!("Failed to resolve plugin directory: {e}")))?;
+    let path = tokio::fs::canonicalize(base.join(relative))
+        .await
+        .map_err(|_| PluginError::FileNotFound)?;
+    if path.starts_with(&base) && path.is_file() {
+        Ok(path)
+    } else {
+        Err(PluginError::FileNotFound)
+    }
+}
+
+/// Streams a file inside `base` from disk, with support for `Range`/`If-Range`,
+/// `ETag`/`Last-Modified` and a `Content-Disposition` based on its type
+pub async fn send_file(req: &HttpRequest, base: &Path, relative: &str) -> HttpResponse {
+    let path = match confine(base, relative).await {
+        Ok(path) => path,
+        Err(err) => return err.to_response(),
+    };
+    match NamedFile::open_async(&path).await {
+        Ok(file) => file.into_response(req),
+        Err(e) => PluginError::InternalError(format!("Failed to open `{}`: {e}", path.display())).to_response(),
+    }
+}
+
+/// Replaces a plugin's response with the file it refers to, if any.
+/// A `Content-Disposition` set by the plugin is kept.
+async fn resolve_file(req: &HttpRequest, res: HttpResponse, base: &Path) -> HttpResponse {
+    let Some(relative) = res.headers().get(X_TCLOUD_FILE) else {
+        return res;
+    };
+    let Ok(relative) = relative.to_str() else {
+        return PluginError::InvalidPath.to_response();
+    };
+    let mut file = send_file(req, base, relative).await;
+    if let Some(disposition) = res.headers().get(CONTENT_DISPOSITION) {
+        if file.status().is_success() {
+            file.headers_mut().insert(CONTENT_DISPOSITION, disposition.clone());
+        }
+    }
+    file
+}
+
+/// Name of the plugin whose routes are being served, set on its scope
+#[derive(Clone)]
+pub struct PluginName(pub String);
+
+/// User making a request to a plugin's routes and the plugin's data path and storage for that user
+pub struct PluginContext {
+    pub name: String,
+    pub user: Option<User>,
+    pub path: PathBuf,
+    /// [`None`] for requests without a user
+    pub kv: Option<KvStore>,
+}
+
+impl PluginContext {
+    /// Streams a file from the plugin's data directory, see [`send_file`]
+    pub async fn send_file(&self, req: &HttpRequest, relative: &str) -> HttpResponse {
+        send_file(req, &self.path, relative).await
+    }
+
+    /// Calls other plugins' services on behalf of the user, see [`ServiceCall`]
+    pub fn services<'a>(&'a self, plugins: &'a Plugins) -> ServiceCall<'a> {
+        plugins.services(&self.name, &self.user)
+    }
+}
+
+impl FromRequest for PluginContext {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let internal = |msg: &str| error::ErrorInternalServerError(msg.to_string());
+            let name = req
+                .app_data::<PluginName>()
+                .ok_or_else(|| internal("PluginContext used outside of a plugin's routes"))?
+                .0
+                .clone();
+            let pool = req
+                .app_data::<web::Data<Pool>>()
+                .ok_or_else(|| internal("Database missing from app data"))?;
+            let user = get_user(pool, &req, req.get_identity().ok())
+                .await
+                .map_err(|resp| error::InternalError::from_response("", resp))?;
+            if let Some(plugins) = req.app_data::<web::Data<Plugins>>() {
+                if let Err(err) = plugins.authorize(&name, &user) {
+                    return Err(error::InternalError::from_response("", err.to_response()).into());
+                }
+            }
+            // Reads such as downloads or polling are not audited, they would flood the log
+            if !req.method().is_safe() {
+                audit::record(
+                    pool,
+                    Event::PluginCall,
+                    user.as_ref().map(|u| u.name.as_str()),
+                    Some(&utils::get_ip(&req)),
+                    Some(format!("plugin: {name}, {} {}", req.method(), req.path())),
+                )
+                .await;
+            }
+            let path = plugins::plugin_path(&user, name.clone());
+            let kv = user.as_ref().map(|user| KvStore::new(Pool::clone(pool), name.clone(), user));
+            Ok(Self { name, user, path, kv })
+        })
+    }
+}
+
+/// Refuses requests to the routes of plugin `name` from users who cannot use it, before its handlers run
+pub async fn authorize(
+    name: String,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let (Some(pool), Some(plugins)) = (req.app_data::<web::Data<Pool>>(), req.app_data::<web::Data<Plugins>>()) else {
+        let err = PluginError::InternalError("Database or plugins missing from app data".into());
+        return Ok(req.into_response(err.to_response()).map_into_right_body());
+    };
+    let user = match get_user(pool, req.request(), req.get_identity().ok()).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(req.into_response(resp).map_into_right_body()),
+    };
+    if let Err(err) = plugins.authorize(&name, &user) {
+        return Ok(req.into_response(err.to_response()).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Lists the plugins the user can use, with their details and status
+#[get("/plugins")]
+pub async fn list(req: HttpRequest, pool: web::Data<Pool>, plugins: web::Data<Plugins>, user: Option<Identity>) -> impl Responder {
+    let user = match get_user(&pool, &req, user).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
+    };
+    let mut info = plugins.info(&user);
+    plugins.check_health(&mut info).await;
+    HttpResponse::Ok().content_type("application/json").body(json!(info).to_string())
+}
+
 /// Handles plugins
 #[post("/p/{plugin}")]
 pub async fn handler(
+    req: HttpRequest,
     pool: web::Data<Pool>,
     plugin: web::Path<String>,
     body: web::Json<Json>,
     plugins: web::Data<Plugins>,
     user: Option<Identity>,
 ) -> impl Responder {
-    log::info!("a");
     let pool = pool.into_inner();
     let plugin = plugin.into_inner();
     let plugins = plugins.into_inner();
     let body = body.into_inner();
-    let user: Option<User> = match user {
-        Some(user) => match user.id() {
-            Ok(name) => match is_admin(&pool, &name).await {
-                Ok(is_admin) => Some(User { name, is_admin }),
-                Err(e) => return e.to_response(),
-            },
-            Err(err) => return utils::id_err_into(err),
-        },
-        None => None,
+    let user = match get_user(&pool, &req, user).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
     };
-    plugins.request(plugin, user, body).await
+    audit::record(
+        &pool,
+        Event::PluginCall,
+        user.as_ref().map(|u| u.name.as_str()),
+        Some(&utils::get_ip(&req)),
+        Some(format!("plugin: {plugin}")),
+    )
+    .await;
+    let path = plugins::plugin_path(&user, plugin.clone());
+    let res = plugins.request(plugin, user, body).await;
+    resolve_file(&req, res, &path).await
 }
 
 #[post("/up/{plugin}")]
 pub async fn file(
+    req: HttpRequest,
     pool: web::Data<Pool>,
     plugins: web::Data<Plugins>,
     plugin: web::Path<String>,
@@ -86,15 +279,62 @@ pub async fn file(
     let pool = pool.into_inner();
     let plugin = plugin.into_inner();
     let plugins = plugins.into_inner();
-    let user: Option<User> = match user {
-        Some(user) => match user.id() {
-            Ok(name) => match is_admin(&pool, &name).await {
-                Ok(is_admin) => Some(User { name, is_admin }),
-                Err(e) => return e.to_response(),
-            },
-            Err(err) => return utils::id_err_into(err),
-        },
-        None => None,
+    let user = match get_user(&pool, &req, user).await {
+        Ok(user) => user,
+        Err(resp) => return resp,
     };
-    plugins.file(plugin, user, form).await
+    audit::record(
+        &pool,
+        Event::PluginCall,
+        user.as_ref().map(|u| u.name.as_str()),
+        Some(&utils::get_ip(&req)),
+        Some(format!("plugin: {plugin}")),
+    )
+    .await;
+    let path = plugins::plugin_path(&user, plugin.clone());
+    let res = plugins.file(plugin, user, form).await;
+    resolve_file(&req, res, &path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Creates `base/dir/file.txt` and `outside.txt` next to `base` in a temporary directory, removed when dropped
+    fn tree() -> (TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let base = root.path().join("base");
+        std::fs::create_dir_all(base.join("dir")).unwrap();
+        std::fs::write(base.join("dir/file.txt"), b"inside").unwrap();
+        std::fs::write(root.path().join("outside.txt"), b"outside").unwrap();
+        (root, base)
+    }
+
+    #[actix_web::test]
+    async fn confines_files_to_base() {
+        let (root, base) = tree();
+        assert!(confine(&base, "dir/file.txt").await.is_ok());
+        assert!(confine(&base, "./dir/file.txt").await.is_ok());
+        assert!(matches!(confine(&base, "../outside.txt").await, Err(PluginError::InvalidPath)));
+        assert!(matches!(
+            confine(&base, "dir/../../outside.txt").await,
+            Err(PluginError::InvalidPath)
+        ));
+        let absolute = root.path().join("outside.txt");
+        assert!(matches!(
+            confine(&base, absolute.to_str().unwrap()).await,
+            Err(PluginError::InvalidPath)
+        ));
+        assert!(matches!(confine(&base, "dir").await, Err(PluginError::FileNotFound)));
+        assert!(matches!(confine(&base, "missing.txt").await, Err(PluginError::FileNotFound)));
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn refuses_symlinks_leaving_base() {
+        let (root, base) = tree();
+        std::os::unix::fs::symlink(root.path().join("outside.txt"), base.join("link.txt")).unwrap();
+        assert!(matches!(confine(&base, "link.txt").await, Err(PluginError::FileNotFound)));
+    }
 }
@@ -19,12 +19,14 @@
 //
 // Email: hex0x0000@protonmail.com
 
+use crate::audit::{self, Event};
 use crate::config;
 use crate::database;
 use crate::token::{self, error::TokenError};
+use crate::utils::get_ip;
 use actix_identity::error::GetIdentityError;
 use actix_identity::Identity;
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
 use async_sqlite::Pool;
 use serde::Deserialize;
 use tcloud_library::error::ErrToResponse;
@@ -59,17 +61,27 @@ async fn check_admin(pool: &Pool, username: String) -> Result<(), HttpResponse>
 
 /// Creates a new token
 #[post("/new")]
-pub async fn new(user: Identity, pool: web::Data<Pool>, info: web::Json<NewToken>) -> impl Responder {
+pub async fn new(req: HttpRequest, user: Identity, pool: web::Data<Pool>, info: web::Json<NewToken>) -> impl Responder {
     if let Some(registration) = config!(registration) {
         let pool = pool.into_inner();
         let username = get_user!(user.id());
-        if let Err(e) = check_admin(&pool, username).await {
+        if let Err(e) = check_admin(&pool, username.clone()).await {
             return e;
         }
         match database::token::create_token(&pool, registration, info.into_inner().duration).await {
-            Ok((token, duration)) => HttpResponse::Ok()
-                .content_type("application/json")
-                .body(json!({"token": token, "duration": duration}).to_string()),
+            Ok((token, duration)) => {
+                audit::record(
+                    &pool,
+                    Event::TokenCreated,
+                    Some(&username),
+                    Some(&get_ip(&req)),
+                    Some(format!("duration: {duration}s")),
+                )
+                .await;
+                HttpResponse::Ok()
+                    .content_type("application/json")
+                    .body(json!({"token": token, "duration": duration}).to_string())
+            }
             Err(e) => Into::<TokenError>::into(e).to_response(),
         }
     } else {
@@ -78,17 +90,19 @@ pub async fn new(user: Identity, pool: web::Data<Pool>, info: web::Json<NewToken
 }
 
 #[post("/delete")]
-pub async fn delete(user: Identity, pool: web::Data<Pool>, token: web::Json<TokenInfo>) -> impl Responder {
+pub async fn delete(req: HttpRequest, user: Identity, pool: web::Data<Pool>, token: web::Json<TokenInfo>) -> impl Responder {
     if config!(registration).is_some() {
         let username = get_user!(user.id());
         let pool = pool.into_inner();
         let token = token.into_inner();
-        if let Err(e) = check_admin(&pool, username).await {
+        if let Err(e) = check_admin(&pool, username.clone()).await {
             return e;
         }
+        let details = token.id.map(|id| format!("id: {id}"));
         if let Err(e) = token::remove_token(&pool, token.id, token.token).await {
             return e.to_response();
         }
+        audit::record(&pool, Event::TokenDeleted, Some(&username), Some(&get_ip(&req)), details).await;
         HttpResponse::Ok().body("")
     } else {
         HttpResponse::NotFound().body("")
@@ -97,13 +111,14 @@ pub async fn delete(user: Identity, pool: web::Data<Pool>, token: web::Json<Toke
 
 /// Returns a list of every token with their expire dates
 #[get("/list")]
-pub async fn list(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+pub async fn list(req: HttpRequest, user: Identity, pool: web::Data<Pool>) -> impl Responder {
     if config!(registration).is_some() {
         let username = get_user!(user.id());
         let pool = pool.into_inner();
-        if let Err(e) = check_admin(&pool, username).await {
+        if let Err(e) = check_admin(&pool, username.clone()).await {
             return e;
         }
+        audit::record(&pool, Event::TokensListed, Some(&username), Some(&get_ip(&req)), None).await;
         match token::get_all_tokens(&pool).await {
             Ok(tokens) => HttpResponse::Ok()
                 .content_type("application/json")
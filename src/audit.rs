@@ -0,0 +1,180 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+pub mod error;
+use crate::{config, database};
+use async_sqlite::Pool;
+use database::audit::{self, Filter};
+use error::AuditError;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tcloud_library::serde_json::{json, Value};
+use tokio::task;
+
+/// Maximum number of records returned by a single search
+pub const MAX_RESULTS: u32 = 1000;
+
+/// How often old records are removed
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static AUDIT_FILE: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+
+/// Security-relevant events recorded in the audit log
+#[derive(Clone, Copy)]
+pub enum Event {
+    Login,
+    LoginFailed,
+    Register,
+    RegisterFailed,
+    TokenCreated,
+    TokenDeleted,
+    TokenConsumed,
+    TokensListed,
+    AccountDeleted,
+    AuditQueried,
+    AdminAction,
+    PluginCall,
+}
+
+impl Event {
+    const ALL: [Self; 12] = [
+        Self::Login,
+        Self::LoginFailed,
+        Self::Register,
+        Self::RegisterFailed,
+        Self::TokenCreated,
+        Self::TokenDeleted,
+        Self::TokenConsumed,
+        Self::TokensListed,
+        Self::AccountDeleted,
+        Self::AuditQueried,
+        Self::AdminAction,
+        Self::PluginCall,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::LoginFailed => "login_failed",
+            Self::Register => "register",
+            Self::RegisterFailed => "register_failed",
+            Self::TokenCreated => "token_created",
+            Self::TokenDeleted => "token_deleted",
+            Self::TokenConsumed => "token_consumed",
+            Self::TokensListed => "tokens_listed",
+            Self::AccountDeleted => "account_deleted",
+            Self::AuditQueried => "audit_queried",
+            Self::AdminAction => "admin_action",
+            Self::PluginCall => "plugin_call",
+        }
+    }
+
+    pub fn parse(event: &str) -> Result<Self, AuditError> {
+        Self::ALL
+            .into_iter()
+            .find(|e| e.as_str() == event)
+            .ok_or_else(|| AuditError::UnknownEvent(event.into()))
+    }
+}
+
+/// Opens the audit file, if configured
+pub fn init() -> Result<(), String> {
+    let file = match config!(audit).as_ref().and_then(|audit| audit.file.as_ref()) {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open audit file `{path}`: {e}"))?,
+        )),
+        None => None,
+    };
+    AUDIT_FILE
+        .set(file)
+        .expect("Tried to initialize AUDIT_FILE while already initialized. This is a bug");
+    Ok(())
+}
+
+/// Records an event. Failures are logged and never interrupt the request.
+pub async fn record(pool: &Pool, event: Event, user: Option<&str>, ip: Option<&str>, details: Option<String>) {
+    if config!(audit).is_none() {
+        return;
+    }
+    let user = user.map(String::from);
+    let ip = ip.map(String::from);
+    match audit::add_record(pool, event.as_str(), user.clone(), ip.clone(), details.clone()).await {
+        Ok(time) => {
+            if let Some(Some(file)) = AUDIT_FILE.get() {
+                let line = json!({ "time": time, "event": event.as_str(), "user": user, "ip": ip, "details": details });
+                let write = task::spawn_blocking(move || {
+                    file.lock()
+                        .map_err(|e| e.to_string())
+                        .and_then(|mut file| writeln!(file, "{line}").map_err(|e| e.to_string()))
+                });
+                match write.await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => log::error!("Failed to write to audit file: {e}"),
+                    Err(e) => log::error!("Audit file writer failed: {e}"),
+                }
+            }
+        }
+        Err(e) => log::error!("Failed to record `{}` audit event: {e}", event.as_str()),
+    }
+}
+
+/// Searches the audit log
+pub async fn search(pool: &Pool, mut filter: Filter) -> Result<Vec<Value>, AuditError> {
+    if let Some(event) = &filter.event {
+        Event::parse(event)?;
+    }
+    filter.limit = filter.limit.min(MAX_RESULTS);
+    audit::search(pool, filter)
+        .await
+        .map(|records| {
+            records
+                .into_iter()
+                .map(|r| json!({ "id": r.id, "time": r.time, "event": r.event, "user": r.user, "ip": r.ip, "details": r.details }))
+                .collect()
+        })
+        .map_err(|e| AuditError::InternalError(e.to_string()))
+}
+
+/// Periodically removes records older than the configured retention
+pub fn start_retention(pool: Pool) {
+    let Some(days) = config!(audit).as_ref().and_then(|audit| audit.retention_days) else {
+        return;
+    };
+    actix_web::rt::spawn(async move {
+        let mut timer = actix_web::rt::time::interval(RETENTION_INTERVAL);
+        loop {
+            timer.tick().await;
+            match audit::remove_older_than(&pool, days).await {
+                Ok(0) => (),
+                Ok(removed) => log::info!("Removed {removed} audit records older than {days} days"),
+                Err(e) => log::error!("Failed to apply audit retention: {e}"),
+            }
+        }
+    });
+}
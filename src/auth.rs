@@ -28,6 +28,7 @@ mod totp;
 use crate::api::auth::Login;
 use crate::config;
 use crate::database;
+use crate::plugins::events::{self, ServerEvent};
 use crate::token::check_token;
 use async_sqlite::Pool;
 use database::auth;
@@ -109,7 +110,10 @@ pub async fn check(pool: &Pool, login: Login) -> Result<String, AuthError> {
 pub async fn add_user(pool: &Pool, username: String, password: Zeroizing<Vec<u8>>, is_admin: bool) -> Result<(), AuthError> {
     check_validity(&username, &password)?;
     let passwd_hash = hash::create(password).await?;
-    auth::add_user(pool, username, passwd_hash, is_admin).await.map_err(|e| e.into())?;
+    auth::add_user(pool, username.clone(), passwd_hash, is_admin)
+        .await
+        .map_err(|e| e.into())?;
+    events::emit(ServerEvent::UserCreated { user: username });
     Ok(())
 }
 
@@ -125,9 +129,11 @@ pub async fn register_user(
     check_validity(&username, &password).map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     check_token(pool, token).await.map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     let passwd_hash = hash::create(password).await.map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
-    auth::add_user(pool, username, passwd_hash, false)
+    auth::add_user(pool, username.clone(), passwd_hash, false)
         .await
         .map_err(|e| Box::new(Into::<AuthError>::into(e)) as Box<dyn ErrToResponse>)?;
+    events::emit(ServerEvent::TokenConsumed { user: username.clone() });
+    events::emit(ServerEvent::UserCreated { user: username });
     Ok(())
 }
 
@@ -137,9 +143,10 @@ pub async fn add_user(pool: &Pool, username: String, password: Zeroizing<Vec<u8>
     check_validity(&username, &password)?;
     let passwd_hash = hash::create(password).await?;
     let totp = self::totp::gen(username.clone())?;
-    auth::add_user(pool, username, passwd_hash, totp.get_url(), is_admin)
+    auth::add_user(pool, username.clone(), passwd_hash, totp.get_url(), is_admin)
         .await
         .map_err(|e| e.into())?;
+    events::emit(ServerEvent::UserCreated { user: username });
     Ok(totp)
 }
 
@@ -156,13 +163,16 @@ pub async fn register_user(
     check_token(pool, token).await.map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     let passwd_hash = hash::create(password).await.map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     let totp = self::totp::gen(username.clone()).map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
-    auth::add_user(pool, username, passwd_hash, totp.get_url(), false)
+    auth::add_user(pool, username.clone(), passwd_hash, totp.get_url(), false)
         .await
         .map_err(|e| Box::new(Into::<AuthError>::into(e)) as Box<dyn ErrToResponse>)?;
+    events::emit(ServerEvent::TokenConsumed { user: username.clone() });
+    events::emit(ServerEvent::UserCreated { user: username });
     Ok(totp)
 }
 
 pub async fn delete_user(pool: &Pool, username: String) -> Result<(), AuthError> {
-    auth::delete_user(&pool, username).await.map_err(|e| e.into())?;
+    auth::delete_user(&pool, username.clone()).await.map_err(|e| e.into())?;
+    events::emit(ServerEvent::UserDeleted { user: username });
     Ok(())
 }
@@ -76,6 +76,31 @@ pub async fn create_user() -> Result<(), String> {
     Ok(())
 }
 
+/// Adds a user without prompting anything
+#[cfg(not(feature = "totp-auth"))]
+pub async fn create_user_with(user: String, password: Zeroizing<Vec<u8>>, is_admin: bool) -> Result<(), String> {
+    let pool = database::init().await.map_err(|e| e.to_string())?;
+    add_user(&pool, user.clone(), password, is_admin).await.map_err(|e| match e {
+        AuthError::InternalError(ref err) => format!("{e}: {err}"),
+        _ => e.to_string(),
+    })?;
+    println!("Successfully added {} {}", if is_admin { "admin" } else { "user" }, user);
+    Ok(())
+}
+
+/// Adds a user without prompting anything and prints its TOTP as a URL
+#[cfg(feature = "totp-auth")]
+pub async fn create_user_with(user: String, password: Zeroizing<Vec<u8>>, is_admin: bool) -> Result<(), String> {
+    let pool = database::init().await.map_err(|e| e.to_string())?;
+    let totp = add_user(&pool, user.clone(), password, is_admin).await.map_err(|e| match e {
+        AuthError::InternalError(ref err) => format!("{e}: {err}"),
+        _ => e.to_string(),
+    })?;
+    println!("Successfully added {} {}", if is_admin { "admin" } else { "user" }, user);
+    println!("TOTP: {}", totp.get_url());
+    Ok(())
+}
+
 #[cfg(feature = "totp-auth")]
 pub async fn create_user() -> Result<(), String> {
     use std::{fs::File, path::PathBuf};
@@ -19,8 +19,9 @@
 //
 // Email: hex0x0000@protonmail.com
 
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
-use std::env::current_exe;
+use std::{collections::HashMap, env::current_exe, net::IpAddr, path::Path};
 use tcloud_library::toml;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -28,12 +29,86 @@ use tokio::sync::OnceCell;
 
 pub static CONFIG: OnceCell<Config> = OnceCell::const_new();
 
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Listener {
+    /// Binds to `host:port`
+    Tcp {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        tls: bool,
+    },
+    /// Binds to a Unix domain socket, always without TLS
+    Unix {
+        path: String,
+        mode: Option<u32>,
+        owner: Option<u32>,
+        group: Option<u32>,
+    },
+    /// Listens on the sockets passed by systemd (LISTEN_FDS)
+    Systemd {
+        #[serde(default)]
+        tls: bool,
+    },
+}
+
+impl Listener {
+    pub fn is_tls(&self) -> bool {
+        match self {
+            Self::Tcp { tls, .. } | Self::Systemd { tls } => *tls,
+            Self::Unix { .. } => false,
+        }
+    }
+}
+
+fn default_shutdown_timeout() -> u64 {
+    60
+}
+
+fn default_plugin_storage_bytes() -> u64 {
+    10_000_000
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Server {
-    pub host: String,
-    pub port: u16,
     pub workers: usize,
-    pub is_behind_proxy: bool,
+    /// Proxies whose forwarding headers are trusted.
+    /// Connections on Unix sockets are always trusted.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Seconds given to in-flight requests to finish on shutdown
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    #[serde(default)]
+    pub listeners: Vec<Listener>,
+    /// Replaced by `listeners`, only read from old configurations
+    #[serde(default, skip_serializing)]
+    pub host: Option<String>,
+    /// Replaced by `listeners`, only read from old configurations
+    #[serde(default, skip_serializing)]
+    pub port: Option<u16>,
+    /// Replaced by `trusted_proxies`, only read from old configurations
+    #[serde(default, skip_serializing)]
+    pub is_behind_proxy: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg(not(feature = "no-tls"))]
+pub enum ClientAuthMode {
+    Optional,
+    Required,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg(not(feature = "no-tls"))]
+pub struct ClientAuth {
+    pub ca_path: String,
+    pub mode: ClientAuthMode,
+    /// Maps the common name of a client certificate's subject to a user
+    #[serde(default)]
+    pub users: HashMap<String, String>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -41,6 +116,7 @@ pub struct Server {
 pub struct Tls {
     pub privkey_path: String,
     pub cert_path: String,
+    pub client_auth: Option<ClientAuth>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -49,13 +125,40 @@ pub struct Registration {
     pub token_size: u8,
 }
 
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg(feature = "normal-log")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg(feature = "normal-log")]
+pub struct Rotation {
+    /// Rotates when the file would grow past this size
+    pub max_size_bytes: Option<u64>,
+    /// Rotates when the file was created this long ago
+    pub max_age_hours: Option<u64>,
+    /// Number of rotated files to keep
+    pub keep: usize,
+    /// Compresses rotated files with gzip
+    pub compress: bool,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Logging {
     pub log_level: String,
     #[cfg(feature = "normal-log")]
+    #[serde(default)]
+    pub format: LogFormat,
+    #[cfg(feature = "normal-log")]
     pub terminal: bool,
     #[cfg(feature = "normal-log")]
     pub file: Option<String>,
+    #[cfg(feature = "normal-log")]
+    pub rotation: Option<Rotation>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -77,6 +180,166 @@ pub struct Durations {
 pub struct Limits {
     pub file_upload_size: usize,
     pub payload_size: usize,
+    /// Bytes of keys and values each plugin can store for a single user
+    #[serde(default = "default_plugin_storage_bytes")]
+    pub plugin_storage_bytes: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Audit {
+    /// Also appends every record to this file as JSON lines
+    pub file: Option<String>,
+    /// Records older than this are removed. If not set they are kept forever.
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Restricts the endpoint to admins. Ignored on a dedicated listener.
+    #[serde(default)]
+    pub admin_only: bool,
+    /// Serves the metrics on a dedicated listener (without TLS) instead of `/metrics`
+    pub listener: Option<Listener>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Security {
+    /// Headers set on every response, unless a handler already did
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    /// `max-age` of `Strict-Transport-Security`, sent only over HTTPS
+    pub hsts_max_age: Option<u64>,
+    /// Requires mutating `/api` requests to echo the `csrf` cookie in `X-CSRF-Token`
+    pub csrf: bool,
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Self {
+            content_security_policy: Some(
+                "default-src 'none'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; \
+                 connect-src 'self'; form-action 'self'; frame-ancestors 'none'; base-uri 'none'"
+                    .into(),
+            ),
+            frame_options: Some("DENY".into()),
+            referrer_policy: Some("no-referrer".into()),
+            hsts_max_age: Some(365 * 24 * 60 * 60),
+            csrf: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Needed by front-ends on another site, requires HTTPS
+    None,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Cors {
+    /// Origins allowed to call `/api`, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// `X-CSRF-Token` is always allowed
+    pub allowed_headers: Vec<String>,
+    /// Allows cookies on cross-origin requests. Cannot be used with any origin.
+    pub credentials: bool,
+    pub max_age_seconds: Option<usize>,
+    /// `SameSite` attribute of the session and CSRF cookies
+    pub cookie_same_site: SameSite,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Requests that can be made at once
+    pub burst: u32,
+    /// Requests given back every minute
+    pub per_minute: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Limit of the requests not matched by any other limit
+    pub global: Option<RateLimit>,
+    /// Limits of the route groups: `ui`, `static`, `auth`, `token`, `admin`, `plugins` and `api`
+    #[serde(default)]
+    pub groups: HashMap<String, RateLimit>,
+    /// Limits of single plugins, they override the `plugins` group
+    #[serde(default)]
+    pub plugins: HashMap<String, RateLimit>,
+    #[serde(default)]
+    pub exempt_ips: Vec<IpNet>,
+    #[serde(default)]
+    pub exempt_users: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccessRules {
+    /// Networks allowed to connect. If empty, any network that is not denied is allowed.
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    /// Networks refused even if they are allowed
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl AccessRules {
+    /// Unknown addresses are allowed only when there are no rules
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|net| net.contains(&ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+            }
+            None => self.allow.is_empty() && self.deny.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Access {
+    pub ui: Option<AccessRules>,
+    pub auth: Option<AccessRules>,
+    pub token: Option<AccessRules>,
+    /// Admin API, audit log and metrics
+    pub admin: Option<AccessRules>,
+    /// Rules of single plugins under `/api/p` and `/api/up`
+    #[serde(default)]
+    pub plugins: HashMap<String, AccessRules>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg(feature = "wasm-plugins")]
+pub struct Wasm {
+    /// Directory of the `.wasm` plugins, each named after its file
+    pub dir: String,
+    /// Memory a plugin can use during a single call
+    pub max_memory_bytes: usize,
+    /// Fuel given to a single call, roughly one unit per instruction
+    pub fuel: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginAccess {
+    /// Anyone, even without logging in
+    Anonymous,
+    /// Logged in users
+    Auth,
+    /// Admins only
+    Admin,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PluginPolicy {
+    /// Can also be changed at runtime by admins
+    pub enabled: bool,
+    pub access: PluginAccess,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -89,11 +352,26 @@ pub struct Config {
     #[cfg(not(feature = "no-tls"))]
     pub tls: Option<Tls>,
     pub registration: Option<Registration>,
+    pub metrics: Option<Metrics>,
+    pub audit: Option<Audit>,
+    #[serde(default)]
+    pub security: Security,
+    pub cors: Option<Cors>,
+    pub rate_limit: Option<RateLimits>,
+    pub access: Option<Access>,
     pub data_directory: String,
     pub session_secret_key_path: String,
     pub limits: Limits,
     pub duration: Durations,
     pub cred_size: CredentialSize,
+    /// Directory of the plugin libraries loaded at startup
+    #[cfg(feature = "dynamic-plugins")]
+    pub plugins_dir: Option<String>,
+    #[cfg(feature = "wasm-plugins")]
+    pub wasm_plugins: Option<Wasm>,
+    /// Plugins without a policy are enabled and allow anonymous access
+    #[serde(default)]
+    pub plugin_policies: HashMap<String, PluginPolicy>,
     pub plugins: toml::Table,
 }
 
@@ -104,24 +382,61 @@ fn get_exec_dir() -> Result<String, String> {
 }
 
 impl Config {
+    /// Converts the options of older versions
+    fn migrate(&mut self) -> Result<(), String> {
+        match (self.server.host.take(), self.server.port.take()) {
+            (Some(host), Some(port)) if self.server.listeners.is_empty() => {
+                #[cfg(not(feature = "no-tls"))]
+                let tls = self.tls.is_some();
+                #[cfg(feature = "no-tls")]
+                let tls = false;
+                self.server.listeners.push(Listener::Tcp { host, port, tls });
+            }
+            (None, None) => (),
+            (Some(_), Some(_)) => return Err("`server.host` and `server.port` cannot be used together with `server.listeners`".into()),
+            _ => return Err("`server.host` and `server.port` must be set together".into()),
+        }
+        // Trusting every peer would let clients spoof their address, the proxies must be listed
+        if self.server.is_behind_proxy.take() == Some(true) && self.server.trusted_proxies.is_empty() {
+            return Err("`server.is_behind_proxy` was replaced by `server.trusted_proxies`, \
+                set it to the addresses of your proxies (e.g. `trusted_proxies = [\"127.0.0.1/32\", \"::1/128\"]`)"
+                .into());
+        }
+        Ok(())
+    }
+
     pub fn default(plugins: toml::Table) -> Result<Self, String> {
         Ok(Self {
             description: env!("CARGO_PKG_DESCRIPTION").to_string(),
             server_name: "Tiny Cloud".into(),
             url_prefix: "tcloud".into(),
             server: Server {
-                host: "127.0.0.1".into(),
-                port: 80,
                 workers: num_cpus::get(),
-                is_behind_proxy: false,
+                trusted_proxies: Vec::new(),
+                shutdown_timeout: default_shutdown_timeout(),
+                listeners: vec![Listener::Tcp {
+                    host: "127.0.0.1".into(),
+                    port: 80,
+                    tls: cfg!(not(feature = "no-tls")),
+                }],
+                host: None,
+                port: None,
+                is_behind_proxy: None,
             },
             logging: {
                 #[cfg(feature = "normal-log")]
                 {
                     Logging {
                         log_level: "info".into(),
+                        format: LogFormat::Text,
                         terminal: true,
                         file: None,
+                        rotation: Some(Rotation {
+                            max_size_bytes: Some(50_000_000),
+                            max_age_hours: None,
+                            keep: 5,
+                            compress: true,
+                        }),
                     }
                 }
                 #[cfg(not(feature = "normal-log"))]
@@ -133,15 +448,44 @@ impl Config {
             tls: Some(Tls {
                 privkey_path: format!("{}/privkey.pem", get_exec_dir()?),
                 cert_path: format!("{}/cert.pem", get_exec_dir()?),
+                client_auth: None,
             }),
             registration: Some(Registration {
                 token_size: 16,
                 token_duration_seconds: 24 * 60 * 60,
             }),
+            metrics: Some(Metrics {
+                admin_only: true,
+                listener: None,
+            }),
+            audit: Some(Audit {
+                file: None,
+                retention_days: Some(365),
+            }),
+            security: Security::default(),
+            cors: None,
+            rate_limit: Some(RateLimits {
+                global: None,
+                groups: HashMap::from([
+                    ("auth".into(), RateLimit { burst: 10, per_minute: 10 }),
+                    (
+                        "plugins".into(),
+                        RateLimit {
+                            burst: 60,
+                            per_minute: 120,
+                        },
+                    ),
+                ]),
+                plugins: HashMap::new(),
+                exempt_ips: Vec::new(),
+                exempt_users: Vec::new(),
+            }),
+            access: None,
             data_directory: format!("{}/data", get_exec_dir()?),
             limits: Limits {
                 file_upload_size: 5_000_000_000,
                 payload_size: 4096,
+                plugin_storage_bytes: default_plugin_storage_bytes(),
             },
             duration: Durations {
                 cookie_minutes: 43200,
@@ -155,6 +499,11 @@ impl Config {
                 max_passwd: 256,
                 min_passwd: 9,
             },
+            #[cfg(feature = "dynamic-plugins")]
+            plugins_dir: None,
+            #[cfg(feature = "wasm-plugins")]
+            wasm_plugins: None,
+            plugin_policies: HashMap::new(),
             plugins,
         })
     }
@@ -168,25 +517,28 @@ pub async fn open(path: String) -> Result<(), String> {
     file.read_to_string(&mut config)
         .await
         .map_err(|e| format!("Failed to read config file `{path}`: {e}"))?;
-    CONFIG
-        .set(toml::from_str(&config).map_err(|e| format!("Failed to read config file `{path}`: {e}"))?)
-        .expect("Config has already been opened. This is a bug");
+    let mut config: Config = toml::from_str(&config).map_err(|e| format!("Failed to read config file `{path}`: {e}"))?;
+    config.migrate().map_err(|e| format!("Invalid config file `{path}`: {e}"))?;
+    CONFIG.set(config).expect("Config has already been opened. This is a bug");
     Ok(())
 }
 
-pub async fn write_default(plugins: toml::Table) -> Result<(), String> {
-    let mut path = current_exe().map_err(|e| format!("Failed to get executable's path: {e}"))?;
-    path.pop();
-    path.push("default.toml");
+pub async fn write(path: impl AsRef<Path>, config: &Config) -> Result<(), String> {
     let mut file = File::create(path).await.map_err(|e| format!("Failed to create config file: {e}"))?;
-    let default = Config::default(plugins)?;
-    let default = toml::to_string(&default).map_err(|e| format!("Failed to serialize config: {e}"))?;
-    file.write_all(default.as_bytes())
+    let config = toml::to_string(config).map_err(|e| format!("Failed to serialize config: {e}"))?;
+    file.write_all(config.as_bytes())
         .await
         .map_err(|e| format!("Failed to write config: {e}"))?;
     Ok(())
 }
 
+pub async fn write_default(plugins: toml::Table) -> Result<(), String> {
+    let mut path = current_exe().map_err(|e| format!("Failed to get executable's path: {e}"))?;
+    path.pop();
+    path.push("default.toml");
+    write(path, &Config::default(plugins)?).await
+}
+
 pub fn get() -> &'static Config {
     CONFIG
         .get()
@@ -19,24 +19,32 @@
 //
 // Email: hex0x0000@protonmail.com
 
+pub mod audit;
 pub mod auth;
 pub mod error;
+pub mod kv;
 pub mod token;
 pub mod utils;
 use crate::{config, plugins};
 use async_sqlite::{JournalMode, Pool, PoolBuilder};
+use audit::AUDIT_TABLE;
 use auth::{get_all_usernames, USERS_TABLE};
 use error::DBError;
+use kv::KV_TABLE;
 use std::path::PathBuf;
 use token::TOKEN_TABLE;
 use tokio::fs;
 
 fn tables() -> String {
+    let mut tables = format!("BEGIN;{USERS_TABLE};{KV_TABLE};");
     if config!(registration).is_some() {
-        format!("BEGIN;{USERS_TABLE};{TOKEN_TABLE};COMMIT;")
-    } else {
-        format!("BEGIN;{USERS_TABLE};COMMIT;")
+        tables.push_str(&format!("{TOKEN_TABLE};"));
     }
+    if let Some(settings) = config!(audit) {
+        tables.push_str(&format!("{AUDIT_TABLE};{};", audit::delete_guard(settings.retention_days)));
+    }
+    tables.push_str("COMMIT;");
+    tables
 }
 
 async fn create_user_dir(user: &str) -> Result<(), DBError> {
@@ -53,6 +61,23 @@ async fn create_user_dir(user: &str) -> Result<(), DBError> {
     Ok(())
 }
 
+/// Creates the directories of a plugin for every user and for requests without a user
+pub async fn create_plugin_dirs(pool: &Pool, plugin: &str) -> Result<(), DBError> {
+    let data_path = PathBuf::from(config!(data_directory));
+    let mut dirs: Vec<PathBuf> = get_all_usernames(pool)
+        .await?
+        .into_iter()
+        .map(|user| data_path.join("users").join(user).join(plugin))
+        .collect();
+    dirs.push(data_path.join("unauth").join(plugin));
+    for dir in dirs {
+        fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| DBError::IOError(format!("Failed to create plugin directory: {e}")))?;
+    }
+    Ok(())
+}
+
 async fn delete_user_dir(user: &str) -> Result<(), DBError> {
     let mut data_dir = PathBuf::from(config!(data_directory));
     data_dir.push("users");
@@ -105,3 +130,14 @@ pub async fn init() -> Result<Pool, DBError> {
 
     Ok(pool)
 }
+
+/// Checkpoints the WAL into the database and closes the pool.
+/// Must be called after the server has stopped.
+pub async fn close(pool: Pool) -> Result<(), DBError> {
+    pool.conn(|conn| conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);"))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to checkpoint database: {e}")))?;
+    pool.close()
+        .await
+        .map_err(|e| DBError::IOError(format!("Failed to close database: {e}")))
+}
@@ -0,0 +1,155 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::{error::DBError, utils::now};
+use async_sqlite::{
+    rusqlite::{self, named_params},
+    Pool,
+};
+use sql_minifier::macros::minify_sql;
+
+/// A recorded security event.
+pub struct Record {
+    pub id: i64,
+    pub time: i64,
+    pub event: String,
+    pub user: Option<String>,
+    pub ip: Option<String>,
+    pub details: Option<String>,
+}
+
+/// Filters used when searching the audit log. Every field is optional.
+#[derive(Default)]
+pub struct Filter {
+    pub user: Option<String>,
+    pub event: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: u32,
+}
+
+pub const AUDIT_TABLE: &str = minify_sql!(
+    "
+CREATE TABLE IF NOT EXISTS audit (
+    id          INTEGER PRIMARY KEY,
+    time        INTEGER NOT NULL,
+    event       TEXT    NOT NULL,
+    user        TEXT,
+    ip          TEXT,
+    details     TEXT
+);
+CREATE INDEX IF NOT EXISTS audit_time ON audit (time);
+CREATE TRIGGER IF NOT EXISTS audit_append_only BEFORE UPDATE ON audit
+BEGIN
+    SELECT RAISE(ABORT, 'audit log is append-only');
+END"
+);
+
+/// Prevents records from being deleted, except the ones older than the retention.
+/// Recreated at every start, so that it follows the configured retention.
+pub fn delete_guard(retention_days: Option<u64>) -> String {
+    let condition = match retention_days {
+        Some(days) => format!(" WHEN OLD.time >= CAST(strftime('%s', 'now') AS INTEGER) - {}", days * 24 * 60 * 60),
+        None => String::new(),
+    };
+    format!(
+        "DROP TRIGGER IF EXISTS audit_no_delete;\
+        CREATE TRIGGER audit_no_delete BEFORE DELETE ON audit{condition} \
+        BEGIN SELECT RAISE(ABORT, 'audit records can only be removed by the retention'); END"
+    )
+}
+
+const INSERT_RECORD: &str =
+    minify_sql!("INSERT INTO audit (time, event, user, ip, details) VALUES (:time, :event, :user, :ip, :details)");
+
+const SEARCH_RECORDS: &str = minify_sql!(
+    "
+SELECT id, time, event, user, ip, details FROM audit
+WHERE (:user IS NULL OR user = :user)
+    AND (:event IS NULL OR event = :event)
+    AND (:since IS NULL OR time >= :since)
+    AND (:until IS NULL OR time <= :until)
+ORDER BY id DESC
+LIMIT :limit"
+);
+
+/// Appends a record to the audit log and returns its time
+pub async fn add_record(
+    pool: &Pool,
+    event: &'static str,
+    user: Option<String>,
+    ip: Option<String>,
+    details: Option<String>,
+) -> Result<u64, DBError> {
+    let time = now()?;
+    pool.conn(move |conn| {
+        conn.execute(
+            INSERT_RECORD,
+            named_params! {
+                ":time": time,
+                ":event": event,
+                ":user": user,
+                ":ip": ip,
+                ":details": details,
+            },
+        )
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to add audit record: {e}")))?;
+    Ok(time)
+}
+
+/// Searches the audit log, newest records first
+pub async fn search(pool: &Pool, filter: Filter) -> Result<Vec<Record>, DBError> {
+    pool.conn(move |conn| {
+        let mut stmt = conn.prepare(SEARCH_RECORDS)?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":user": filter.user,
+                ":event": filter.event,
+                ":since": filter.since,
+                ":until": filter.until,
+                ":limit": filter.limit,
+            },
+            |row| {
+                Ok(Record {
+                    id: row.get(0)?,
+                    time: row.get(1)?,
+                    event: row.get(2)?,
+                    user: row.get(3)?,
+                    ip: row.get(4)?,
+                    details: row.get(5)?,
+                })
+            },
+        )?;
+        rows.collect::<Result<Vec<Record>, rusqlite::Error>>()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to search audit log: {e}")))
+}
+
+/// Removes records older than `days` days
+pub async fn remove_older_than(pool: &Pool, days: u64) -> Result<usize, DBError> {
+    let limit = now()?.saturating_sub(days * 24 * 60 * 60);
+    pool.conn(move |conn| conn.execute("DELETE FROM audit WHERE time < ?1", [limit]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to remove old audit records: {e}")))
+}
@@ -19,7 +19,7 @@
 //
 // Email: hex0x0000@protonmail.com
 
-use super::error::DBError;
+use super::{error::DBError, kv::DELETE_USER_KV};
 use async_sqlite::{
     rusqlite::{self, named_params, ErrorCode, OptionalExtension},
     Error, Pool,
@@ -163,10 +163,15 @@ pub async fn get_all_usernames(pool: &Pool) -> Result<Vec<String>, DBError> {
 /// Deletes a user from database
 pub async fn delete_user(pool: &Pool, username: String) -> Result<(), DBError> {
     let username_clone = username.clone();
-    pool.conn(move |conn| conn.execute("DELETE FROM users WHERE username=?1", [username_clone]))
-        .await
-        .map_err(|e| DBError::ExecError(format!("Failed to delete user: {e}")))?;
-    log::info!("Deleted user '{username}'");
+    pool.conn_mut(move |conn| {
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM users WHERE username=?1", [&username_clone])?;
+        tx.execute(DELETE_USER_KV, [&username_clone])?;
+        tx.commit()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete user: {e}")))?;
+    log::info!("Deleted user '{username}' and their plugin storage");
     super::delete_user_dir(&username).await?;
     log::info!("Deleted user directory of '{username}'");
     Ok(())
@@ -33,6 +33,8 @@ pub enum DBError {
     UserExists,
     #[error("Time failure: {0}")]
     TimeFailure(String),
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
 }
 
 impl Into<AuthError> for DBError {
@@ -52,6 +54,9 @@ impl Into<TokenError> for DBError {
 
 impl Into<PluginError> for DBError {
     fn into(self) -> PluginError {
-        PluginError::InternalError(self.to_string())
+        match self {
+            Self::QuotaExceeded => PluginError::QuotaExceeded,
+            _ => PluginError::InternalError(self.to_string()),
+        }
     }
 }
@@ -0,0 +1,189 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::error::DBError;
+use async_sqlite::{
+    rusqlite::{self, named_params, Connection, OptionalExtension},
+    Pool,
+};
+use sql_minifier::macros::minify_sql;
+
+/// Entries stored by plugins for each user
+pub const KV_TABLE: &str = minify_sql!(
+    "
+CREATE TABLE IF NOT EXISTS plugin_kv (
+    plugin      TEXT    NOT NULL,
+    user        TEXT    NOT NULL,
+    key         TEXT    NOT NULL,
+    value       BLOB    NOT NULL,
+    PRIMARY KEY(plugin, user, key)
+)"
+);
+
+const GET_VALUE: &str = minify_sql!("SELECT value FROM plugin_kv WHERE plugin=:plugin AND user=:user AND key=:key");
+
+const SET_VALUE: &str = minify_sql!(
+    "INSERT INTO plugin_kv (plugin, user, key, value) VALUES (:plugin, :user, :key, :value)
+    ON CONFLICT(plugin, user, key) DO UPDATE SET value=excluded.value"
+);
+
+const DELETE_VALUE: &str = minify_sql!("DELETE FROM plugin_kv WHERE plugin=:plugin AND user=:user AND key=:key");
+
+const LIST_KEYS: &str = minify_sql!(
+    "SELECT key FROM plugin_kv WHERE plugin=:plugin AND user=:user AND substr(key, 1, length(:prefix))=:prefix ORDER BY key"
+);
+
+const USAGE: &str = minify_sql!(
+    "SELECT COALESCE(SUM(length(CAST(key AS BLOB)) + length(value)), 0) FROM plugin_kv WHERE plugin=:plugin AND user=:user"
+);
+
+/// Removes every entry of a user, for every plugin
+pub const DELETE_USER_KV: &str = minify_sql!("DELETE FROM plugin_kv WHERE user=?1");
+
+/// Change applied by [`apply`]
+#[derive(Debug, Clone)]
+pub enum KvOp {
+    Set(String, Vec<u8>),
+    Delete(String),
+}
+
+/// Gets the value of a key
+pub async fn get(pool: &Pool, plugin: String, user: String, key: String) -> Result<Option<Vec<u8>>, DBError> {
+    pool.conn(move |conn| {
+        conn.query_row(GET_VALUE, named_params! { ":plugin": plugin, ":user": user, ":key": key }, |row| {
+            row.get(0)
+        })
+        .optional()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to get plugin value: {e}")))
+}
+
+/// Lists the keys starting with `prefix`, sorted
+pub async fn list(pool: &Pool, plugin: String, user: String, prefix: String) -> Result<Vec<String>, DBError> {
+    pool.conn(move |conn| {
+        let mut stmt = conn.prepare(LIST_KEYS)?;
+        let rows = stmt.query_map(named_params! { ":plugin": plugin, ":user": user, ":prefix": prefix }, |row| {
+            row.get(0)
+        })?;
+        rows.collect()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to list plugin keys: {e}")))
+}
+
+fn usage_of(conn: &Connection, plugin: &str, user: &str) -> rusqlite::Result<u64> {
+    conn.query_row(USAGE, named_params! { ":plugin": plugin, ":user": user }, |row| row.get(0))
+}
+
+/// Returns the bytes used by the keys and values of the user
+pub async fn usage(pool: &Pool, plugin: String, user: String) -> Result<u64, DBError> {
+    pool.conn(move |conn| usage_of(conn, &plugin, &user))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to get plugin storage usage: {e}")))
+}
+
+/// Applies every change in a single transaction.
+/// Nothing is changed if the user's storage would grow over `quota` bytes,
+/// changes that free space are always applied.
+pub async fn apply(pool: &Pool, plugin: String, user: String, ops: Vec<KvOp>, quota: u64) -> Result<(), DBError> {
+    let committed = pool
+        .conn_mut(move |conn| {
+            let tx = conn.transaction()?;
+            let before = usage_of(&tx, &plugin, &user)?;
+            for op in ops {
+                match op {
+                    KvOp::Set(key, value) => tx.execute(
+                        SET_VALUE,
+                        named_params! { ":plugin": plugin, ":user": user, ":key": key, ":value": value },
+                    )?,
+                    KvOp::Delete(key) => tx.execute(DELETE_VALUE, named_params! { ":plugin": plugin, ":user": user, ":key": key })?,
+                };
+            }
+            let used = usage_of(&tx, &plugin, &user)?;
+            if used > quota && used > before {
+                return Ok(false);
+            }
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to update plugin storage: {e}")))?;
+    if !committed {
+        return Err(DBError::QuotaExceeded);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_sqlite::PoolBuilder;
+    use tempfile::TempDir;
+
+    /// Opens a database in a temporary directory, removed when dropped
+    async fn pool() -> (Pool, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = PoolBuilder::new().path(dir.path().join("kv.db")).open().await.unwrap();
+        pool.conn(|conn| conn.execute(KV_TABLE, [])).await.unwrap();
+        (pool, dir)
+    }
+
+    fn set(key: &str, len: usize) -> KvOp {
+        KvOp::Set(key.into(), vec![0; len])
+    }
+
+    #[actix_web::test]
+    async fn enforces_quota() {
+        let (pool, _dir) = pool().await;
+        let (plugin, user) = (String::from("plugin"), String::from("user"));
+        apply(&pool, plugin.clone(), user.clone(), vec![set("a", 9)], 20).await.unwrap();
+        assert_eq!(usage(&pool, plugin.clone(), user.clone()).await.unwrap(), 10);
+        // Every change is refused when the storage would grow over the quota
+        let res = apply(&pool, plugin.clone(), user.clone(), vec![set("b", 4), set("c", 9)], 20).await;
+        assert!(matches!(res, Err(DBError::QuotaExceeded)));
+        assert_eq!(list(&pool, plugin.clone(), user.clone(), String::new()).await.unwrap(), vec!["a"]);
+        // Other users and plugins have their own quota
+        apply(&pool, plugin.clone(), "other".into(), vec![set("a", 19)], 20).await.unwrap();
+        apply(&pool, "other".into(), user.clone(), vec![set("a", 19)], 20).await.unwrap();
+        assert_eq!(usage(&pool, plugin.clone(), user.clone()).await.unwrap(), 10);
+        pool.close().await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn frees_space_over_quota() {
+        let (pool, _dir) = pool().await;
+        let (plugin, user) = (String::from("plugin"), String::from("user"));
+        apply(&pool, plugin.clone(), user.clone(), vec![set("a", 9), set("b", 9)], 20)
+            .await
+            .unwrap();
+        // The quota was lowered, changes that do not free space are refused
+        let res = apply(&pool, plugin.clone(), user.clone(), vec![set("a", 10)], 10).await;
+        assert!(matches!(res, Err(DBError::QuotaExceeded)));
+        apply(&pool, plugin.clone(), user.clone(), vec![set("a", 5)], 10).await.unwrap();
+        apply(&pool, plugin.clone(), user.clone(), vec![KvOp::Delete("b".into())], 10)
+            .await
+            .unwrap();
+        assert_eq!(usage(&pool, plugin.clone(), user.clone()).await.unwrap(), 6);
+        assert_eq!(get(&pool, plugin, user, "a".into()).await.unwrap(), Some(vec![0; 5]));
+        pool.close().await.unwrap();
+    }
+}
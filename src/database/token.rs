@@ -114,6 +114,13 @@ pub async fn get_all_tokens(pool: &Pool) -> Result<Vec<Token>, DBError> {
     .map_err(|e| DBError::ExecError(format!("Failed to get tokens: {e}")))
 }
 
+/// Counts saved tokens
+pub async fn count_tokens(pool: &Pool) -> Result<i64, DBError> {
+    pool.conn(|conn| conn.query_row("SELECT COUNT(*) FROM tokens", [], |row| row.get(0)))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to count tokens: {e}")))
+}
+
 /// Removes a token
 pub async fn delete_token(pool: &Pool, token: String) -> Result<(), DBError> {
     pool.conn(move |conn| conn.execute("DELETE FROM tokens WHERE token = ?1", [token]))
@@ -31,6 +31,12 @@ pub enum RequestError {
     QueryError(String),
     #[error("Invalid Multipart request: {0}")]
     MultipartError(String),
+    #[error("Missing or invalid CSRF token")]
+    CsrfError,
+    #[error("Too many requests, try again later")]
+    RateLimited,
+    #[error("Access denied from this network")]
+    AccessDenied,
 }
 
 impl ErrToResponse for RequestError {
@@ -43,6 +49,9 @@ impl ErrToResponse for RequestError {
             Self::JsonError(_) => stringify!(JsonError),
             Self::QueryError(_) => stringify!(QueryError),
             Self::MultipartError(_) => stringify!(MultipartError),
+            Self::CsrfError => stringify!(CsrfError),
+            Self::RateLimited => stringify!(RateLimited),
+            Self::AccessDenied => stringify!(AccessDenied),
         }
     }
 
@@ -51,7 +60,11 @@ impl ErrToResponse for RequestError {
     }
 
     fn http_code(&self) -> HttpResponseBuilder {
-        HttpResponse::BadRequest()
+        match &self {
+            Self::CsrfError | Self::AccessDenied => HttpResponse::Forbidden(),
+            Self::RateLimited => HttpResponse::TooManyRequests(),
+            _ => HttpResponse::BadRequest(),
+        }
     }
 
     fn handle(&self) {
@@ -0,0 +1,246 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{auth, config, plugins::Plugins};
+use rand::{rngs::OsRng, RngCore};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+use tcloud_library::tiny_args::*;
+use zeroize::Zeroizing;
+
+/// Size of the generated session secret key
+const SECRET_KEY_SIZE: usize = 64;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+#[cfg(feature = "no-tls")]
+const DEFAULT_PORT: &str = "80";
+
+#[cfg(not(feature = "no-tls"))]
+const DEFAULT_PORT: &str = "443";
+
+/// Validity of the generated self-signed certificate
+#[cfg(not(feature = "no-tls"))]
+const CERT_DAYS: u32 = 365;
+
+/// Returns the `init` subcommand
+pub fn subcmd<'a>() -> CommandBuilder<&'a str> {
+    Command::create("init", "Sets up a new instance of Tiny Cloud and exits")
+        .arg(
+            arg! { -c, --config },
+            ArgType::String,
+            "Path where the configuration will be written (default: ./config.toml)",
+        )
+        .arg(arg! { --host }, ArgType::String, "Host the server will bind to")
+        .arg(arg! { --port }, ArgType::String, "Port the server will bind to")
+        .arg(arg! { --data-dir }, ArgType::String, "Path to the data directory")
+        .arg(
+            arg! { --self-signed },
+            ArgType::Flag,
+            "Generates a self-signed certificate for the configured host",
+        )
+        .arg(arg! { --admin }, ArgType::String, "Username of the first admin")
+        .arg(
+            arg! { --admin-password-file },
+            ArgType::String,
+            "File containing the password of the first admin",
+        )
+        .arg(
+            arg! { --non-interactive },
+            ArgType::Flag,
+            "Never prompts, uses the given flags and the defaults for everything else",
+        )
+        .arg(arg! { --force }, ArgType::Flag, "Overwrites already existing files")
+        .arg(arg! { -h, --help }, ArgType::Flag, "Shows this help and exits")
+}
+
+/// Handles the `init` subcommand. Returns true if it was called.
+pub async fn handle_args(parsed: &ParsedCommand, plugins: &Plugins) -> bool {
+    if parsed.parents.is_empty() || parsed.name != "init" {
+        return false;
+    }
+    if parsed.args.get(arg!(--help)).is_some() {
+        println!("{}", parsed.help);
+    } else if let Err(e) = init(parsed, plugins).await {
+        eprintln!("Failed to initialize Tiny Cloud: {e}");
+    }
+    true
+}
+
+fn prompt(msg: &str, default: &str) -> Result<String, String> {
+    print!("{msg} [{default}]: ");
+    io::stdout().flush().map_err(|e| format!("Failed to write prompt: {e}"))?;
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(|e| format!("Failed to read input: {e}"))?;
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(default.into())
+    } else {
+        Ok(input.into())
+    }
+}
+
+fn confirm(msg: &str) -> Result<bool, String> {
+    Ok(prompt(msg, "y/n")?.to_lowercase() == "y")
+}
+
+/// Fails if the file exists and cannot be overwritten
+fn check_writable(path: &str, force: bool) -> Result<(), String> {
+    if !force && Path::new(path).exists() {
+        return Err(format!("`{path}` already exists, use --force to overwrite it"));
+    }
+    Ok(())
+}
+
+/// Writes a file readable only by its owner
+fn write_private(path: &str, data: &[u8], force: bool) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| format!("Failed to write `{path}`: {e}"))
+}
+
+async fn init(parsed: &ParsedCommand, plugins: &Plugins) -> Result<(), String> {
+    let interactive = parsed.args.get(arg! { --non-interactive }).is_none();
+    let force = parsed.args.get(arg!(--force)).is_some();
+    let config_path = match parsed.args.get(arg!(--config)) {
+        Some(path) => path.value().string(),
+        None => "./config.toml".into(),
+    };
+    check_writable(&config_path, force)?;
+
+    // Gathers everything before writing, so that nothing is left half set up
+    let mut config = config::Config::default(plugins.default_configs())?;
+    let host = match parsed.args.get(arg!(--host)) {
+        Some(host) => host.value().string(),
+        None if interactive => prompt("Host", DEFAULT_HOST)?,
+        None => DEFAULT_HOST.into(),
+    };
+    let port = match parsed.args.get(arg!(--port)) {
+        Some(port) => port.value().string(),
+        None if interactive => prompt("Port", DEFAULT_PORT)?,
+        None => DEFAULT_PORT.into(),
+    };
+    config.server.listeners = vec![config::Listener::Tcp {
+        port: port.parse().map_err(|e| format!("Invalid port `{port}`: {e}"))?,
+        host: host.clone(),
+        tls: cfg!(not(feature = "no-tls")),
+    }];
+    config.data_directory = match parsed.args.get(arg! { --data-dir }) {
+        Some(path) => path.value().string(),
+        None if interactive => prompt("Data directory", &config.data_directory)?,
+        None => config.data_directory,
+    };
+    check_writable(&config.session_secret_key_path, force)?;
+
+    #[cfg(not(feature = "no-tls"))]
+    let self_signed = match &config.tls {
+        Some(tls) => {
+            let self_signed =
+                parsed.args.get(arg! { --self-signed }).is_some() || (interactive && confirm("Generate a self-signed certificate?")?);
+            if self_signed {
+                check_writable(&tls.privkey_path, force)?;
+                check_writable(&tls.cert_path, force)?;
+            }
+            self_signed
+        }
+        None => false,
+    };
+
+    let admin = match parsed.args.get(arg!(--admin)) {
+        Some(admin) => {
+            let password = match parsed.args.get(arg! { --admin-password-file }) {
+                Some(path) => {
+                    let path = path.value().string();
+                    let password =
+                        Zeroizing::new(std::fs::read_to_string(&path).map_err(|e| format!("Failed to read `{path}`: {e}"))?);
+                    Zeroizing::new(password.trim_end_matches(['\r', '\n']).as_bytes().to_vec())
+                }
+                None if interactive => Zeroizing::new(
+                    rpassword::prompt_password("Password: ")
+                        .map_err(|e| format!("Failed to read password: {e}"))?
+                        .into_bytes(),
+                ),
+                None => return Err("--admin requires --admin-password-file in non-interactive mode".into()),
+            };
+            Admin::Given(admin.value().string(), password)
+        }
+        None if interactive && confirm("Create the first admin now?")? => Admin::Prompt,
+        None => Admin::Skip,
+    };
+
+    // Writes the configuration
+    config::write(&config_path, &config).await?;
+    println!("Configuration written to `{config_path}`");
+    config::open(config_path).await?;
+
+    // Generates the session secret key
+    let secret_key = {
+        let mut key = Zeroizing::new(vec![0u8; SECRET_KEY_SIZE]);
+        OsRng.fill_bytes(&mut key);
+        key
+    };
+    write_private(config!(session_secret_key_path), &secret_key, force)?;
+    println!("Session secret key written to `{}`", config!(session_secret_key_path));
+
+    // Generates a self-signed certificate
+    #[cfg(not(feature = "no-tls"))]
+    if let (Some(tls), true) = (config!(tls), self_signed) {
+        let (cert, key) = crate::tls::self_signed(&host, CERT_DAYS)?;
+        write_private(&tls.privkey_path, &key, force)?;
+        write_private(&tls.cert_path, &cert, force)?;
+        println!("Self-signed certificate written to `{}`", tls.cert_path);
+    }
+
+    // Creates the first admin
+    match admin {
+        Admin::Given(name, password) => auth::cli::create_user_with(name, password, true).await?,
+        Admin::Prompt => auth::cli::create_user().await?,
+        Admin::Skip => (),
+    }
+
+    println!("Tiny Cloud is ready to start.");
+    Ok(())
+}
+
+/// How the first admin is created
+enum Admin {
+    Given(String, Zeroizing<Vec<u8>>),
+    Prompt,
+    Skip,
+}
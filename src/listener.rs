@@ -0,0 +1,145 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::config;
+use crate::config::Listener;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::{
+    env, fs,
+    os::unix::{
+        fs::{chown, PermissionsExt},
+        io::{FromRawFd, IntoRawFd, RawFd},
+        net::UnixListener,
+    },
+    process,
+};
+
+/// First file descriptor passed by systemd
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+pub enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// An opened socket ready to be used by the server
+pub struct Bound {
+    pub socket: Socket,
+    pub tls: bool,
+    pub name: String,
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, mode: Option<u32>, owner: Option<u32>, group: Option<u32>) -> Result<UnixListener, String> {
+    // Removes the socket left by a previous run
+    if fs::metadata(path).is_ok() {
+        fs::remove_file(path).map_err(|e| format!("Failed to remove old socket `{path}`: {e}"))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("Failed to bind to socket `{path}`: {e}"))?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .map_err(|e| format!("Failed to set mode of socket `{path}`: {e}"))?;
+    }
+    if owner.is_some() || group.is_some() {
+        chown(path, owner, group).map_err(|e| format!("Failed to set owner of socket `{path}`: {e}"))?;
+    }
+    Ok(listener)
+}
+
+/// Takes the sockets passed by systemd's socket activation.
+/// Environment variables are removed so that they are not inherited.
+#[cfg(unix)]
+fn systemd_sockets() -> Result<Vec<Socket>, String> {
+    let pid = env::var("LISTEN_PID").map_err(|_| "Socket activation was configured but LISTEN_PID is not set".to_string())?;
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Err("LISTEN_PID does not match the server's PID".into());
+    }
+    let fds = env::var("LISTEN_FDS").map_err(|_| "Socket activation was configured but LISTEN_FDS is not set".to_string())?;
+    let fds: RawFd = fds.parse().map_err(|e| format!("Invalid LISTEN_FDS `{fds}`: {e}"))?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    Ok((LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(|fd| {
+            // SAFETY: systemd passes ownership of these descriptors to the process
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            if listener.local_addr().is_ok() {
+                Socket::Tcp(listener)
+            } else {
+                // Not an internet socket, so it must be a Unix one
+                Socket::Unix(unsafe { UnixListener::from_raw_fd(listener.into_raw_fd()) })
+            }
+        })
+        .collect())
+}
+
+/// Opens every configured listener
+pub fn open(listeners: &[Listener]) -> Result<Vec<Bound>, String> {
+    let mut bound = Vec::new();
+    for listener in listeners {
+        if listener.is_tls() {
+            #[cfg(feature = "no-tls")]
+            return Err("A listener has TLS enabled but TLS support was not compiled in".into());
+            #[cfg(not(feature = "no-tls"))]
+            if config!(tls).is_none() {
+                return Err("A listener has TLS enabled but the [tls] section is missing".into());
+            }
+        }
+        match listener {
+            Listener::Tcp { host, port, tls } => {
+                let name = format!("{host}:{port}");
+                bound.push(Bound {
+                    socket: Socket::Tcp(TcpListener::bind(&name).map_err(|e| format!("Failed to bind to {name}: {e}"))?),
+                    tls: *tls,
+                    name,
+                });
+            }
+            #[cfg(unix)]
+            Listener::Unix { path, mode, owner, group } => bound.push(Bound {
+                socket: Socket::Unix(bind_unix(path, *mode, *owner, *group)?),
+                tls: false,
+                name: format!("unix:{path}"),
+            }),
+            #[cfg(unix)]
+            Listener::Systemd { tls } => {
+                for (i, socket) in systemd_sockets()?.into_iter().enumerate() {
+                    let tls = *tls && matches!(socket, Socket::Tcp(_));
+                    bound.push(Bound {
+                        socket,
+                        tls,
+                        name: format!("systemd socket #{i}"),
+                    });
+                }
+            }
+            #[cfg(not(unix))]
+            Listener::Unix { .. } | Listener::Systemd { .. } => {
+                return Err("Unix sockets and systemd socket activation are only supported on Unix".into())
+            }
+        }
+    }
+    if bound.is_empty() {
+        return Err("No listener was configured".into());
+    }
+    Ok(bound)
+}
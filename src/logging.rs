@@ -19,59 +19,216 @@
 //
 // Email: hex0x0000@protonmail.com
 
+mod filter;
+#[cfg(feature = "normal-log")]
+mod rotate;
+
 use crate::config;
-use log::LevelFilter;
+#[cfg(feature = "normal-log")]
+use crate::config::LogFormat;
+use filter::Filter;
+use log::{Log, Metadata, Record};
+#[cfg(feature = "normal-log")]
+use rotate::RotatingFile;
 #[cfg(feature = "normal-log")]
 use simplelog::*;
+use std::{future::Future, sync::RwLock};
 #[cfg(feature = "normal-log")]
-use std::fs::OpenOptions;
+use std::{
+    io::{self, Write},
+    sync::{Mutex, OnceLock},
+};
 #[cfg(feature = "syslog")]
 use syslog::{BasicLogger, Formatter3164};
 #[cfg(feature = "systemd-log")]
 use systemd_journal_logger::JournalLog;
+#[cfg(feature = "normal-log")]
+use tcloud_library::serde_json::json;
+#[cfg(feature = "normal-log")]
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Filter applied to every backend, can be changed at runtime
+static FILTER: RwLock<Filter> = RwLock::new(Filter::off());
+
+/// Log file, kept to be reopened on SIGUSR1
+#[cfg(feature = "normal-log")]
+static LOG_FILE: OnceLock<RotatingFile> = OnceLock::new();
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `f` with `id` attached to every line it logs
+pub async fn with_request_id<F: Future>(id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
+}
+
+/// Returns the ID of the request currently being handled, if any
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Applies the filter and prefixes the request ID to every line logged during a request
+struct Logger {
+    inner: Box<dyn Log>,
+    prefix_request_id: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.read().is_ok_and(|filter| filter.enabled(metadata)) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match request_id().filter(|_| self.prefix_request_id) {
+            Some(id) => self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{id}] {}", record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Parses a filter like `info,tiny_cloud::auth=debug` and applies it to every backend
+pub fn set_filter(spec: &str) -> Result<(), String> {
+    let filter: Filter = spec.parse()?;
+    log::set_max_level(filter.max_level());
+    *FILTER.write().map_err(|_| "Log filter lock was poisoned".to_string())? = filter;
+    Ok(())
+}
+
+/// Returns the filter currently applied
+pub fn get_filter() -> String {
+    FILTER
+        .read()
+        .map(|filter| filter.to_string())
+        .unwrap_or_else(|_| "Log filter lock was poisoned".into())
+}
+
+/// Logs every line as a JSON object, with the request ID as a separate field
+#[cfg(feature = "normal-log")]
+struct JsonLogger {
+    outputs: Vec<Mutex<Box<dyn Write + Send>>>,
+}
 
-fn get_filter(level: &str) -> LevelFilter {
-    match level {
-        "error" => LevelFilter::Error,
-        "warn" => LevelFilter::Warn,
-        "info" => LevelFilter::Info,
-        "debug" => LevelFilter::Debug,
-        "trace" => LevelFilter::Trace,
-        _ => {
-            eprintln!("Disabling level filtering");
-            LevelFilter::Off
+#[cfg(feature = "normal-log")]
+impl Log for JsonLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = json!({
+            "time": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "request_id": request_id(),
+            "message": record.args().to_string(),
+        })
+        .to_string();
+        for output in &self.outputs {
+            if let Ok(mut output) = output.lock() {
+                let _ = writeln!(output, "{line}");
+            }
         }
     }
+
+    fn flush(&self) {
+        for output in &self.outputs {
+            if let Ok(mut output) = output.lock() {
+                let _ = output.flush();
+            }
+        }
+    }
+}
+
+/// Installs the logger. Backends log everything, filtering is done by [`Logger`].
+fn install(inner: Box<dyn Log>, prefix_request_id: bool) -> Result<(), String> {
+    set_filter(config!(logging.log_level))?;
+    log::set_boxed_logger(Box::new(Logger { inner, prefix_request_id })).map_err(|e| format!("Failed to set up logger: {e}"))
 }
 
 mutually_exclusive_features::exactly_one_of!("normal-log", "syslog", "systemd-log");
 
+#[cfg(feature = "normal-log")]
+fn open_log_file(file: &str) -> Result<RotatingFile, String> {
+    let log_file =
+        RotatingFile::new(file, config!(logging.rotation).clone()).map_err(|e| format!("Failed to open log file `{file}`: {e}"))?;
+    LOG_FILE
+        .set(log_file.clone())
+        .map_err(|_| "Log file was already opened. This is a bug".to_string())?;
+    Ok(log_file)
+}
+
+/// Reopens the log file on SIGUSR1, so that it can be rotated by external tools
+#[cfg(all(feature = "normal-log", unix))]
+pub fn reopen_on_signal() -> Result<(), String> {
+    use actix_web::rt::{
+        self,
+        signal::unix::{signal, SignalKind},
+    };
+
+    let Some(log_file) = LOG_FILE.get() else {
+        return Ok(());
+    };
+    let mut stream = signal(SignalKind::user_defined1()).map_err(|e| format!("Failed to listen for SIGUSR1: {e}"))?;
+    rt::spawn(async move {
+        while stream.recv().await.is_some() {
+            match log_file.reopen() {
+                Ok(()) => log::info!("Log file reopened"),
+                Err(e) => eprintln!("Failed to reopen log file: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
 #[cfg(feature = "normal-log")]
 pub fn init_logging() -> Result<(), String> {
-    let level_filter = get_filter(config!(logging.log_level));
-    if let Some(file) = config!(logging.file) {
-        CombinedLogger::init(vec![
-            if *config!(logging.terminal) {
-                TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto)
-            } else {
-                SimpleLogger::new(level_filter, Config::default())
-            },
-            WriteLogger::new(
-                level_filter,
-                Config::default(),
-                OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(file)
-                    .map_err(|e| format!("Failed to open log file `{file}`: {e}"))?,
-            ),
-        ])
-        .map_err(|e| format!("Failed to initialize combined logger: {e}"))?;
+    // Backends log everything, filtering is done by `Logger`
+    let level_filter = LevelFilter::Trace;
+    if *config!(logging.format) == LogFormat::Json {
+        let mut outputs: Vec<Mutex<Box<dyn Write + Send>>> = Vec::new();
+        if *config!(logging.terminal) {
+            outputs.push(Mutex::new(Box::new(io::stderr())));
+        }
+        if let Some(file) = config!(logging.file) {
+            outputs.push(Mutex::new(Box::new(open_log_file(file)?)));
+        }
+        // JSON lines carry the request ID in their own field
+        install(Box::new(JsonLogger { outputs }), false)?;
+    } else if let Some(file) = config!(logging.file) {
+        install(
+            CombinedLogger::new(vec![
+                if *config!(logging.terminal) {
+                    TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto)
+                } else {
+                    SimpleLogger::new(level_filter, Config::default())
+                },
+                WriteLogger::new(level_filter, Config::default(), open_log_file(file)?),
+            ]),
+            true,
+        )?;
     } else if *config!(logging.terminal) {
-        TermLogger::init(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto)
-            .map_err(|e| format!("Failed to initialize terminal logger: {e}"))?;
+        install(
+            TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
+            true,
+        )?;
     } else {
-        SimpleLogger::init(level_filter, Config::default()).map_err(|e| format!("Failed to initialize simple logger: {e}"))?;
+        install(SimpleLogger::new(level_filter, Config::default()), true)?;
     }
     Ok(())
 }
@@ -79,18 +236,13 @@ pub fn init_logging() -> Result<(), String> {
 #[cfg(feature = "syslog")]
 pub fn init_logging() -> Result<(), String> {
     let logger = syslog::unix(Formatter3164::default()).map_err(|e| format!("Failed to connect to syslog: {e}"))?;
-    log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
-        .map(|()| log::set_max_level(get_filter(config!(logging.log_level))))
-        .map_err(|e| format!("Failed to set up syslog logger: {e}"))?;
-    Ok(())
+    install(Box::new(BasicLogger::new(logger)), true)
 }
 
 #[cfg(feature = "systemd-log")]
 pub fn init_logging() -> Result<(), String> {
-    JournalLog::new()
-        .map_err(|e| format!("Failed to create journal log: {e}"))?
-        .install()
-        .map_err(|e| format!("Failed to install journal log: {e}"))?;
-    log::set_max_level(get_filter(config!(logging.log_level)));
-    Ok(())
+    install(
+        Box::new(JournalLog::new().map_err(|e| format!("Failed to create journal log: {e}"))?),
+        true,
+    )
 }
@@ -0,0 +1,140 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use log::{LevelFilter, Metadata};
+use std::{fmt, str::FromStr};
+
+/// Level filter with optional per-module overrides,
+/// e.g. `info,tiny_cloud::auth=debug,actix_web=warn`
+#[derive(Clone)]
+pub struct Filter {
+    default: LevelFilter,
+    /// Sorted from the most to the least specific module
+    modules: Vec<(String, LevelFilter)>,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level.trim()).map_err(|_| format!("Invalid log level `{level}`"))
+}
+
+impl Filter {
+    pub const fn off() -> Self {
+        Self {
+            default: LevelFilter::Off,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        let level = self
+            .modules
+            .iter()
+            .find(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level);
+        metadata.level() <= level
+    }
+
+    /// Most verbose level enabled by any module
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, LevelFilter::max)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::off();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => filter.modules.push((module.trim().to_string(), parse_level(level)?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        filter.modules.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{module}={}", level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn enabled(filter: &Filter, target: &str, level: Level) -> bool {
+        filter.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        let filter: Filter = " info, actix_web=WARN ,tiny_cloud::auth=debug,".parse().unwrap();
+        assert_eq!(filter.to_string(), "info,tiny_cloud::auth=debug,actix_web=warn");
+        assert_eq!(Filter::from_str("").unwrap().to_string(), "off");
+    }
+
+    #[test]
+    fn most_specific_module_wins() {
+        let filter: Filter = "warn,tiny_cloud::auth=trace,tiny_cloud=info".parse().unwrap();
+        assert!(enabled(&filter, "tiny_cloud::auth::cli", Level::Trace));
+        assert!(enabled(&filter, "tiny_cloud::auth", Level::Trace));
+        assert!(enabled(&filter, "tiny_cloud::api", Level::Info));
+        assert!(!enabled(&filter, "tiny_cloud::api", Level::Debug));
+        assert!(enabled(&filter, "actix_web", Level::Warn));
+        assert!(!enabled(&filter, "actix_web", Level::Info));
+    }
+
+    #[test]
+    fn matches_whole_module_names() {
+        let filter: Filter = "off,tiny_cloud::auth=debug".parse().unwrap();
+        assert!(enabled(&filter, "tiny_cloud::auth", Level::Debug));
+        assert!(!enabled(&filter, "tiny_cloud::authx", Level::Error));
+        assert!(!enabled(&filter, "tiny_cloud", Level::Error));
+    }
+
+    #[test]
+    fn rejects_invalid_levels() {
+        assert!(Filter::from_str("loud").is_err());
+        assert!(Filter::from_str("info,tiny_cloud=loud").is_err());
+        assert!(Filter::from_str("info,tiny_cloud=").is_err());
+    }
+
+    #[test]
+    fn max_level_covers_every_module() {
+        assert_eq!(Filter::from_str("warn,tiny_cloud=trace").unwrap().max_level(), LevelFilter::Trace);
+        assert_eq!(Filter::from_str("debug,actix_web=error").unwrap().max_level(), LevelFilter::Debug);
+        assert_eq!(Filter::off().max_level(), LevelFilter::Off);
+    }
+}
@@ -0,0 +1,288 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::config::Rotation;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+struct State {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Start of the current file's age, see [`started_at`]
+    started_at: SystemTime,
+    rotation: Option<Rotation>,
+    /// Compression of the last rotated file, still running or not joined yet
+    compressing: Option<JoinHandle<()>>,
+}
+
+/// Log file that rotates itself by size and age and can be reopened
+/// after being moved by an external tool (e.g. logrotate)
+#[derive(Clone)]
+pub struct RotatingFile(Arc<Mutex<State>>);
+
+/// Returns when the file was created, so that its age survives restarts and reopens.
+/// Falls back to the current time on file systems that do not record it.
+fn started_at(metadata: &fs::Metadata) -> SystemTime {
+    metadata.created().unwrap_or_else(|_| SystemTime::now())
+}
+
+fn open(path: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    let metadata = file.metadata()?;
+    Ok((file, metadata.len(), started_at(&metadata)))
+}
+
+/// Path of the `n`th rotated file
+fn rotated(path: &Path, n: usize, compressed: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+/// Compresses a rotated file. On failure the uncompressed file is kept.
+fn compress(path: PathBuf) {
+    let mut gz_path = path.clone().into_os_string();
+    gz_path.push(".gz");
+    let result = (|| -> io::Result<()> {
+        let mut input = File::open(&path)?;
+        let mut output = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+        io::copy(&mut input, &mut output)?;
+        output.finish()?;
+        fs::remove_file(&path)
+    })();
+    if let Err(e) = result {
+        eprintln!("Failed to compress rotated log file `{}`: {e}", path.display());
+        if path.exists() {
+            let _ = fs::remove_file(&gz_path);
+        }
+    }
+}
+
+impl State {
+    fn reopen(&mut self) -> io::Result<()> {
+        let (file, size, started_at) = open(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.started_at = started_at;
+        Ok(())
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        let Some(rotation) = &self.rotation else {
+            return false;
+        };
+        let too_big = rotation
+            .max_size_bytes
+            .is_some_and(|max| self.size > 0 && self.size + incoming as u64 > max);
+        let too_old = rotation.max_age_hours.is_some_and(|hours| {
+            self.started_at
+                .elapsed()
+                .is_ok_and(|age| age >= Duration::from_secs(hours * 60 * 60))
+        });
+        too_big || too_old
+    }
+
+    /// Shifts every rotated file by one, dropping the oldest, and starts a new file.
+    /// Files whose compression failed are shifted along with the compressed ones.
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(rotation) = self.rotation.clone() else {
+            return Ok(());
+        };
+        self.file.flush()?;
+        // The previous file must be done compressing before it is moved
+        if let Some(compressing) = self.compressing.take() {
+            if compressing.join().is_err() {
+                eprintln!("Log file compression panicked");
+            }
+        }
+        if rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let variants: &[bool] = if rotation.compress { &[true, false] } else { &[false] };
+            for &compressed in variants {
+                let _ = fs::remove_file(rotated(&self.path, rotation.keep, compressed));
+                for n in (1..rotation.keep).rev() {
+                    let from = rotated(&self.path, n, compressed);
+                    if from.exists() {
+                        fs::rename(from, rotated(&self.path, n + 1, compressed))?;
+                    }
+                }
+            }
+            let first = rotated(&self.path, 1, false);
+            fs::rename(&self.path, &first)?;
+            if rotation.compress {
+                // Compression can be slow, it must not block logging
+                self.compressing = Some(thread::spawn(move || compress(first)));
+            }
+        }
+        self.reopen()
+    }
+}
+
+impl RotatingFile {
+    pub fn new(path: &str, rotation: Option<Rotation>) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let (file, size, started_at) = open(&path)?;
+        Ok(Self(Arc::new(Mutex::new(State {
+            path,
+            file,
+            size,
+            started_at,
+            rotation,
+            compressing: None,
+        }))))
+    }
+
+    fn state(&self) -> io::Result<MutexGuard<'_, State>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Log file lock was poisoned"))
+    }
+
+    /// Reopens the file at its path
+    #[cfg(unix)]
+    pub fn reopen(&self) -> io::Result<()> {
+        self.state()?.reopen()
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state()?;
+        if state.needs_rotation(buf.len()) {
+            if let Err(e) = state.rotate() {
+                eprintln!("Failed to rotate log file `{}`: {e}", state.path.display());
+                // Retries only after another full period
+                state.started_at = SystemTime::now();
+            }
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state()?.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn file(keep: usize, compress: bool) -> (TempDir, PathBuf, RotatingFile) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.log");
+        let rotation = Rotation {
+            max_size_bytes: Some(10),
+            max_age_hours: Some(1),
+            keep,
+            compress,
+        };
+        let file = RotatingFile::new(path.to_str().unwrap(), Some(rotation)).unwrap();
+        (dir, path, file)
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn read_gz(path: &Path) -> String {
+        let mut content = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn rotates_by_size_and_prunes() {
+        let (_dir, path, mut file) = file(2, false);
+        // A line bigger than the limit is written to an empty file without rotating
+        file.write_all(b"first line").unwrap();
+        file.write_all(b"second").unwrap();
+        assert_eq!(read(&rotated(&path, 1, false)), "first line");
+        file.write_all(b"third").unwrap();
+        file.write_all(b"fourth").unwrap();
+        assert_eq!(read(&path), "fourth");
+        assert_eq!(read(&rotated(&path, 1, false)), "third");
+        assert_eq!(read(&rotated(&path, 2, false)), "second");
+        assert!(!rotated(&path, 3, false).exists());
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let (_dir, path, mut file) = file(1, false);
+        file.write_all(b"old").unwrap();
+        file.state().unwrap().started_at -= Duration::from_secs(2 * 60 * 60);
+        file.write_all(b"new").unwrap();
+        assert_eq!(read(&path), "new");
+        assert_eq!(read(&rotated(&path, 1, false)), "old");
+    }
+
+    #[test]
+    fn compresses_rotated_files() {
+        let (_dir, path, mut file) = file(2, true);
+        for line in ["first line", "second line", "third line"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        // Each rotation waits for the previous compression, so the older files are done
+        assert_eq!(read_gz(&rotated(&path, 2, true)), "first line");
+        file.write_all(b"fourth line").unwrap();
+        assert_eq!(read_gz(&rotated(&path, 2, true)), "second line");
+        assert!(!rotated(&path, 3, true).exists());
+        assert!(!rotated(&path, 2, false).exists());
+    }
+
+    #[test]
+    fn shifts_files_that_failed_to_compress() {
+        let (_dir, path, mut file) = file(3, true);
+        fs::write(rotated(&path, 1, false), "failed").unwrap();
+        fs::write(rotated(&path, 1, true), "compressed").unwrap();
+        file.write_all(b"first line").unwrap();
+        file.write_all(b"second").unwrap();
+        assert_eq!(read(&rotated(&path, 2, false)), "failed");
+        assert_eq!(read(&rotated(&path, 2, true)), "compressed");
+    }
+
+    #[test]
+    fn keeps_file_when_compression_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.log.1");
+        fs::write(&path, "content").unwrap();
+        // The compressed file cannot be created over a directory
+        fs::create_dir(dir.path().join("server.log.1.gz")).unwrap();
+        compress(path.clone());
+        assert_eq!(read(&path), "content");
+    }
+}
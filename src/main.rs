@@ -20,13 +20,20 @@
 // Email: hex0x0000@protonmail.com
 
 mod api;
+mod audit;
 mod auth;
 mod config;
 mod database;
 mod error;
+mod init;
+mod listener;
 mod logging;
+mod metrics;
+mod middlewares;
 mod plugins;
+mod ratelimit;
 mod server;
+mod systemd;
 #[cfg(not(feature = "no-tls"))]
 mod tls;
 mod token;
@@ -60,6 +67,7 @@ async fn main() {
             "Writes the default configuration and exits",
         )
         .arg(arg! { -h, --help }, ArgType::Flag, "Shows this help and exits");
+    cmd = cmd.subcommand(init::subcmd());
     cmd = plugins.add_subcmds(cmd);
     let cmd = cmd.build();
 
@@ -71,6 +79,10 @@ async fn main() {
         }
     };
 
+    if init::handle_args(&parsed, &plugins).await {
+        return;
+    }
+
     if plugins.handle_args(&parsed) {
         return;
     }
@@ -97,6 +109,11 @@ async fn main() {
         return;
     }
 
+    if let Err(e) = plugins.load() {
+        eprintln!("Failed to load plugins: {e}");
+        return;
+    }
+
     if parsed.args.get(arg! { --create-user }).is_some() {
         if let Err(e) = auth::cli::create_user().await {
             eprintln!("Failed to create user: {e}");
@@ -109,6 +126,11 @@ async fn main() {
         return;
     }
 
+    if let Err(e) = audit::init() {
+        log::error!("Failed to initialize audit log: {e}");
+        return;
+    }
+
     let secret_key = {
         let path = config!(session_secret_key_path);
         match fs::read(path).await {
@@ -0,0 +1,272 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{
+    config, database,
+    listener::{self, Bound, Socket},
+    utils,
+};
+use actix_identity::{Identity, IdentityExt};
+use actix_web::{
+    body::MessageBody,
+    dev::{Server, ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, App, Error, HttpResponse, HttpServer,
+};
+use async_sqlite::Pool;
+use prometheus::{Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Sessions without requests for this long are not counted as active
+/// when `duration.visit_minutes` is not set
+const DEFAULT_SESSION_IDLE: Duration = Duration::from_secs(30 * 60);
+
+/// Maximum number of users tracked as active, new ones are not counted past it
+const MAX_SEEN: usize = 10_000;
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    plugin_requests: IntCounterVec,
+    plugin_duration: HistogramVec,
+    logins: IntCounterVec,
+    upload_bytes: IntCounterVec,
+    active_sessions: IntGauge,
+    db_latency: Gauge,
+    tokens: IntGauge,
+    /// Last time each user made a request
+    last_seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("tcloud".into()), None)?,
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+                &["route", "method", "status"],
+            )?,
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route and method"),
+                &["route", "method"],
+            )?,
+            plugin_requests: IntCounterVec::new(
+                Opts::new("plugin_requests_total", "Plugin requests by plugin, kind and status"),
+                &["plugin", "kind", "status"],
+            )?,
+            plugin_duration: HistogramVec::new(
+                HistogramOpts::new("plugin_request_duration_seconds", "Plugin request latency by plugin and kind"),
+                &["plugin", "kind"],
+            )?,
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Login attempts by result and failure reason"),
+                &["result", "reason"],
+            )?,
+            upload_bytes: IntCounterVec::new(Opts::new("upload_bytes_total", "Bytes uploaded by plugin"), &["plugin"])?,
+            active_sessions: IntGauge::new("active_sessions", "Users that made a request within the visit deadline")?,
+            db_latency: Gauge::new("db_query_seconds", "Time needed to get a database connection and run `SELECT 1`")?,
+            tokens: IntGauge::new("registration_tokens", "Registration tokens currently saved")?,
+            last_seen: Mutex::new(HashMap::new()),
+        };
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.plugin_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.plugin_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.logins.clone()))?;
+        metrics.registry.register(Box::new(metrics.upload_bytes.clone()))?;
+        metrics.registry.register(Box::new(metrics.active_sessions.clone()))?;
+        metrics.registry.register(Box::new(metrics.db_latency.clone()))?;
+        metrics.registry.register(Box::new(metrics.tokens.clone()))?;
+        Ok(metrics)
+    }
+}
+
+/// Returns the metrics, or [`None`] if they are disabled
+fn get() -> Option<&'static Metrics> {
+    config!(metrics).as_ref()?;
+    Some(METRICS.get_or_init(|| Metrics::new().expect("Failed to create metrics. This is a bug")))
+}
+
+fn session_idle() -> Duration {
+    config!(duration.visit_minutes).map_or(DEFAULT_SESSION_IDLE, |m| Duration::from_secs(m * 60))
+}
+
+/// Marks the user as active. Idle users are dropped once [`MAX_SEEN`] users are tracked.
+fn seen(last_seen: &mut HashMap<String, Instant>, user: String) {
+    if last_seen.len() >= MAX_SEEN && !last_seen.contains_key(&user) {
+        let idle = session_idle();
+        last_seen.retain(|_, seen| seen.elapsed() < idle);
+        if last_seen.len() >= MAX_SEEN {
+            return;
+        }
+    }
+    last_seen.insert(user, Instant::now());
+}
+
+/// Records the latency and status of every request and which users are active
+pub async fn middleware(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(metrics) = get() else {
+        return next.call(req).await;
+    };
+    // Patterns are used instead of paths to keep the number of labels bounded
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let method = req.method().to_string();
+    if let Some(user) = req.get_identity().ok().and_then(|id| id.id().ok()) {
+        seen(
+            &mut metrics.last_seen.lock().expect("Metrics lock was poisoned. This is a bug"),
+            user,
+        );
+    }
+    let start = Instant::now();
+    let res = next.call(req).await?;
+    metrics
+        .http_duration
+        .with_label_values(&[&route, &method])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[&route, &method, res.status().as_str()])
+        .inc();
+    Ok(res)
+}
+
+/// Records a handled plugin request
+pub fn plugin(name: &str, kind: &str, status: u16, elapsed: Duration) {
+    if let Some(metrics) = get() {
+        metrics
+            .plugin_duration
+            .with_label_values(&[name, kind])
+            .observe(elapsed.as_secs_f64());
+        metrics.plugin_requests.with_label_values(&[name, kind, &status.to_string()]).inc();
+    }
+}
+
+/// Records a login attempt. Failures carry the type of the [`AuthError`](crate::auth::error::AuthError).
+pub fn login(result: Result<(), &str>) {
+    if let Some(metrics) = get() {
+        match result {
+            Ok(()) => metrics.logins.with_label_values(&["success", ""]).inc(),
+            Err(reason) => metrics.logins.with_label_values(&["failure", reason]).inc(),
+        }
+    }
+}
+
+/// Records an uploaded file
+pub fn upload(plugin: &str, bytes: usize) {
+    if let Some(metrics) = get() {
+        metrics.upload_bytes.with_label_values(&[plugin]).inc_by(bytes as u64);
+    }
+}
+
+/// Updates the gauges and encodes every metric
+async fn render(pool: &Pool) -> HttpResponse {
+    let Some(metrics) = get() else {
+        return HttpResponse::NotFound().body("");
+    };
+
+    let idle = session_idle();
+    {
+        let mut last_seen = metrics.last_seen.lock().expect("Metrics lock was poisoned. This is a bug");
+        last_seen.retain(|_, seen| seen.elapsed() < idle);
+        metrics.active_sessions.set(last_seen.len() as i64);
+    }
+
+    let start = Instant::now();
+    if let Err(e) = pool.conn(|conn| conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))).await {
+        log::error!("Failed to query database for metrics: {e}");
+    }
+    metrics.db_latency.set(start.elapsed().as_secs_f64());
+
+    if config!(registration).is_some() {
+        match database::token::count_tokens(pool).await {
+            Ok(count) => metrics.tokens.set(count),
+            Err(e) => log::error!("Failed to count tokens for metrics: {e}"),
+        }
+    }
+
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {e}");
+        return HttpResponse::InternalServerError().body("");
+    }
+    HttpResponse::Ok().content_type(encoder.format_type()).body(buffer)
+}
+
+/// Serves the metrics on the main server, only to admins if configured so
+pub async fn endpoint(user: Option<Identity>, pool: web::Data<Pool>) -> HttpResponse {
+    if config!(metrics).as_ref().is_some_and(|m| m.admin_only) {
+        let Some(user) = user else {
+            return HttpResponse::Forbidden().body("");
+        };
+        let username = match user.id() {
+            Ok(username) => username,
+            Err(e) => return utils::id_err_into(e),
+        };
+        match database::auth::is_admin(&pool, username).await {
+            Ok(Some(true)) => (),
+            Ok(_) => return HttpResponse::Forbidden().body(""),
+            Err(e) => {
+                log::error!("Failed to check admin for metrics: {e}");
+                return HttpResponse::InternalServerError().body("");
+            }
+        }
+    }
+    render(&pool).await
+}
+
+async fn scrape(pool: web::Data<Pool>) -> HttpResponse {
+    render(&pool).await
+}
+
+/// Creates the server for the dedicated metrics listener, if configured
+pub fn server(pool: web::Data<Pool>) -> Result<Option<Server>, String> {
+    let Some(listener) = config!(metrics).as_ref().and_then(|m| m.listener.as_ref()) else {
+        return Ok(None);
+    };
+    if listener.is_tls() {
+        return Err("The metrics listener does not support TLS".into());
+    }
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::clone(&pool))
+            .route("/metrics", web::get().to(scrape))
+    })
+    .workers(1)
+    .disable_signals();
+    for Bound { socket, name, .. } in listener::open(std::slice::from_ref(listener))? {
+        log::info!("Serving metrics on {name}");
+        server = match socket {
+            Socket::Tcp(socket) => server.listen(socket),
+            #[cfg(unix)]
+            Socket::Unix(socket) => server.listen_uds(socket),
+        }
+        .map_err(|e| format!("Failed to bind metrics server: {e}"))?;
+    }
+    Ok(Some(server.run()))
+}
@@ -0,0 +1,246 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{config, error::RequestError, logging, utils};
+use actix_cors::Cors;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    cookie::Cookie,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{
+            HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, SET_COOKIE, STRICT_TRANSPORT_SECURITY,
+            X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+        },
+        Method, Uri,
+    },
+    middleware::Next,
+    Error,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use tcloud_library::error::ErrToResponse;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const X_CSRF_TOKEN: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// Name of the cookie holding the CSRF token
+const CSRF_COOKIE: &str = "csrf";
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(len).map(char::from).collect()
+}
+
+/// Maximum length of a request ID given by the client
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Returns the request ID sent by the client if it is safe to log
+fn client_request_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(X_REQUEST_ID)?.to_str().ok()?;
+    (!id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .then(|| id.to_string())
+}
+
+/// Assigns an ID to every request, or keeps the one sent with `X-Request-Id`.
+/// The ID replaces the request's header, so that inner middlewares such as the access log see it,
+/// is attached to every log line of the request and is returned in the response.
+pub async fn request_id(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = client_request_id(&req).unwrap_or_else(|| random_string(16));
+    let value = HeaderValue::from_str(&id).ok();
+    if let Some(value) = &value {
+        req.headers_mut().insert(X_REQUEST_ID, value.clone());
+    }
+    let mut res = logging::with_request_id(id, next.call(req)).await?;
+    if let Some(value) = value {
+        res.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    Ok(res)
+}
+
+/// Marks every cookie as secure when the client is using HTTPS,
+/// even when TLS is terminated by a trusted proxy
+pub async fn secure_cookies(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let https = utils::is_https(req.request());
+    let mut res = next.call(req).await?;
+    if https {
+        let headers = res.headers_mut();
+        let cookies: Vec<HeaderValue> = headers.get_all(SET_COOKIE).cloned().collect();
+        headers.remove(SET_COOKIE);
+        for value in cookies {
+            let secure = value
+                .to_str()
+                .ok()
+                .and_then(|value| Cookie::parse(value).ok())
+                .and_then(|mut cookie| {
+                    cookie.set_secure(true);
+                    HeaderValue::from_str(&cookie.to_string()).ok()
+                });
+            headers.append(SET_COOKIE, secure.unwrap_or(value));
+        }
+    }
+    Ok(res)
+}
+
+/// Sets the security headers from the config on every response
+pub async fn security_headers(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let https = utils::is_https(req.request());
+    let mut res = next.call(req).await?;
+    let security = config!(security);
+    let hsts = security.hsts_max_age.filter(|_| https).map(|age| format!("max-age={age}"));
+    let headers = res.headers_mut();
+    for (name, value) in [
+        (CONTENT_SECURITY_POLICY, security.content_security_policy.as_deref()),
+        (X_FRAME_OPTIONS, security.frame_options.as_deref()),
+        (REFERRER_POLICY, security.referrer_policy.as_deref()),
+        (STRICT_TRANSPORT_SECURITY, hsts.as_deref()),
+        (X_CONTENT_TYPE_OPTIONS, Some("nosniff")),
+    ] {
+        if headers.contains_key(&name) {
+            continue;
+        }
+        if let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.insert(name, value);
+        }
+    }
+    Ok(res)
+}
+
+/// Compares two tokens without leaking where they differ
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Double-submit CSRF protection, first half. Every client gets a random token in the `csrf` cookie,
+/// which is also returned in `X-CSRF-Token`. See [`csrf_check`] for the verification.
+pub async fn csrf(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !*config!(security.csrf) {
+        return next.call(req).await;
+    }
+    let token = req.cookie(CSRF_COOKIE).map(|cookie| cookie.value().to_string());
+    let mut res = next.call(req).await?;
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let token = random_string(32);
+            let cookie = Cookie::build(CSRF_COOKIE, token.clone())
+                .path("/")
+                .same_site(utils::cookie_same_site())
+                .finish();
+            res.response_mut().add_cookie(&cookie)?;
+            token
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&token) {
+        res.headers_mut().insert(X_CSRF_TOKEN, value);
+    }
+    Ok(res)
+}
+
+/// Double-submit CSRF protection, second half. Mutating requests must send the `csrf` cookie back in `X-CSRF-Token`.
+/// Wraps the `/api` scope inside the CORS middleware, so that cross-origin clients can read the refusal.
+pub async fn csrf_check(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    if *config!(security.csrf) && !req.method().is_safe() {
+        let token = req.cookie(CSRF_COOKIE);
+        let header = req.headers().get(X_CSRF_TOKEN).map(|header| header.as_bytes());
+        if !matches!((&token, header), (Some(token), Some(header)) if tokens_match(token.value().as_bytes(), header)) {
+            log::warn!(
+                "client [{}] sent a request without a valid CSRF token",
+                utils::get_ip(req.request())
+            );
+            return Ok(req.into_response(RequestError::CsrfError.to_response()).map_into_right_body());
+        }
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Checks the CORS config, so that building the middleware cannot fail
+pub fn check_cors(cors: &config::Cors) -> Result<(), String> {
+    if cors.credentials && cors.allowed_origins.iter().any(|origin| origin == "*") {
+        return Err("CORS credentials cannot be allowed for any origin".into());
+    }
+    for origin in cors.allowed_origins.iter().filter(|origin| *origin != "*") {
+        // Checked like the CORS middleware does, which panics on the first request otherwise
+        let uri: Uri = origin.parse().map_err(|e| format!("Invalid CORS origin `{origin}`: {e}"))?;
+        if uri.scheme().is_none() || uri.authority().is_none() {
+            return Err(format!("Invalid CORS origin `{origin}`: it must be like `https://example.com`"));
+        }
+        HeaderValue::from_str(origin).map_err(|_| format!("Invalid CORS origin `{origin}`"))?;
+    }
+    for method in &cors.allowed_methods {
+        Method::from_bytes(method.as_bytes()).map_err(|_| format!("Invalid CORS method `{method}`"))?;
+    }
+    for header in &cors.allowed_headers {
+        HeaderName::from_bytes(header.as_bytes()).map_err(|_| format!("Invalid CORS header `{header}`"))?;
+    }
+    Ok(())
+}
+
+/// Builds the CORS middleware of the `/api` scope from the config
+pub fn cors() -> Cors {
+    let Some(config) = config!(cors) else {
+        return Cors::default();
+    };
+    let mut cors = Cors::default()
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(config.allowed_headers.iter().map(String::as_str))
+        .allowed_header(X_CSRF_TOKEN)
+        .expose_headers([X_CSRF_TOKEN, X_REQUEST_ID])
+        .max_age(config.max_age_seconds);
+    for origin in &config.allowed_origins {
+        cors = if origin == "*" {
+            cors.allow_any_origin()
+        } else {
+            cors.allowed_origin(origin)
+        };
+    }
+    if config.credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+/// Refuses clients whose IP is not allowed by the access rules of the route
+pub async fn access_rules(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(access) = config!(access) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let rules = match utils::route_group(req.path()) {
+        ("ui", _) => access.ui.as_ref(),
+        ("auth", _) => access.auth.as_ref(),
+        ("token", _) => access.token.as_ref(),
+        ("admin", _) => access.admin.as_ref(),
+        ("plugins", Some(plugin)) => access.plugins.get(&plugin),
+        _ => None,
+    };
+    if let Some(rules) = rules {
+        let ip = utils::get_ip(req.request());
+        if !rules.allows(ip.parse().ok()) {
+            log::warn!("client [{ip}] was denied access to {}", req.path());
+            return Ok(req.into_response(RequestError::AccessDenied.to_response()).map_into_right_body());
+        }
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
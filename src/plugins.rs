@@ -19,35 +19,171 @@
 //
 // Email: hex0x0000@protonmail.com
 
+#[cfg(feature = "dynamic-plugins")]
+pub mod dynamic;
 pub mod error;
+pub mod events;
+pub mod hooks;
+pub mod kv;
 mod macros;
+pub mod services;
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm;
 use crate::*;
-use actix_web::HttpResponse;
-use api::plugins::FileForm;
+use actix_web::{
+    middleware::from_fn,
+    web::{self, ServiceConfig},
+    HttpResponse,
+};
+use api::plugins::{FileForm, PluginName};
+use config::PluginAccess;
+use error::PluginError;
+use events::ServerEvent;
+use hooks::{Hooks, PluginUi};
+use serde::Serialize;
+use services::ServiceCall;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::{boxed::Box, sync::OnceLock};
+use std::{boxed::Box, sync::RwLock, time::Instant};
 use tcloud_library::plugin::User;
-use tcloud_library::{plugin::Plugin, toml::Table, Json, Toml};
+use tcloud_library::{error::ErrToResponse, plugin::Plugin, toml::Table, Json, Toml};
 
-static PLUGIN_NAMES: OnceLock<Vec<String>> = OnceLock::new();
+static PLUGIN_NAMES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+/// Plugins disabled by their policy or by an admin
+static DISABLED: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Details of a plugin returned to clients
+#[derive(Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Who can use the plugin
+    pub access: PluginAccess,
+    pub endpoints: Vec<String>,
+    /// `ok`, or `unhealthy` if its health check fails. Only set by [`Plugins::check_health`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+}
+
+/// A registered plugin
+enum Loaded {
+    /// Built into the server, with its server hooks
+    Builtin(Box<dyn Hooks>),
+    /// Loaded from a shared library, which cannot implement server hooks
+    #[cfg(feature = "dynamic-plugins")]
+    Dynamic(dynamic::Dynamic),
+    /// Sandboxed WebAssembly module, which only handles requests
+    #[cfg(feature = "wasm-plugins")]
+    Wasm(wasm::WasmPlugin),
+}
+
+impl Loaded {
+    /// Returns the native plugin, or [`None`] for WebAssembly plugins
+    fn plugin(&self) -> Option<&dyn Plugin> {
+        match self {
+            Self::Builtin(plugin) => Some(&**plugin),
+            #[cfg(feature = "dynamic-plugins")]
+            Self::Dynamic(dynamic) => Some(&*dynamic.plugin),
+            #[cfg(feature = "wasm-plugins")]
+            Self::Wasm(_) => None,
+        }
+    }
+
+    fn plugin_mut(&mut self) -> Option<&mut dyn Plugin> {
+        match self {
+            Self::Builtin(plugin) => Some(&mut **plugin),
+            #[cfg(feature = "dynamic-plugins")]
+            Self::Dynamic(dynamic) => Some(&mut *dynamic.plugin),
+            #[cfg(feature = "wasm-plugins")]
+            Self::Wasm(_) => None,
+        }
+    }
+
+    fn hooks(&self) -> Option<&dyn Hooks> {
+        match self {
+            Self::Builtin(plugin) => Some(&**plugin),
+            #[cfg(feature = "dynamic-plugins")]
+            Self::Dynamic(_) => None,
+            #[cfg(feature = "wasm-plugins")]
+            Self::Wasm(_) => None,
+        }
+    }
+
+    async fn request(&self, user: Option<User>, body: Json, path: PathBuf) -> HttpResponse {
+        match self {
+            Self::Builtin(plugin) => plugin.request(user, body, path).await,
+            #[cfg(feature = "dynamic-plugins")]
+            Self::Dynamic(dynamic) => dynamic.plugin.request(user, body, path).await,
+            #[cfg(feature = "wasm-plugins")]
+            Self::Wasm(plugin) => plugin.request(user, body, path).await,
+        }
+    }
+
+    async fn file(&self, user: Option<User>, file: FileForm, path: PathBuf) -> HttpResponse {
+        let info = file.info.into_inner();
+        match self {
+            Self::Builtin(plugin) => plugin.file(user, file.file, info, path).await,
+            #[cfg(feature = "dynamic-plugins")]
+            Self::Dynamic(dynamic) => dynamic.plugin.file(user, file.file, info, path).await,
+            #[cfg(feature = "wasm-plugins")]
+            Self::Wasm(plugin) => plugin.file(user, file.file, info, path).await,
+        }
+    }
+}
 
 pub struct Plugins {
-    plugins: HashMap<String, Box<dyn Plugin>>,
+    plugins: HashMap<String, Loaded>,
+    uis: HashMap<String, PluginUi>,
 }
 
 impl Plugins {
     pub fn new() -> Self {
         let plugins = HashMap::from([plugin!("archive", tcloud_archive::ArchivePlugin)]);
-        PLUGIN_NAMES
-            .set(plugins.keys().cloned().collect())
-            .expect("Tried to initialize PLUGIN_NAMES while already initialized. This is a bug");
-        Self { plugins }
+        *PLUGIN_NAMES.write().expect("Plugin names lock was poisoned. This is a bug") = plugins.keys().cloned().collect();
+        Self {
+            plugins,
+            uis: HashMap::new(),
+        }
+    }
+
+    /// Registers a plugin loaded at runtime
+    #[cfg(any(feature = "dynamic-plugins", feature = "wasm-plugins"))]
+    fn register(&mut self, name: String, plugin: Loaded) -> Result<(), String> {
+        if self.plugins.contains_key(&name) {
+            return Err(format!("Plugin '{name}' is already loaded"));
+        }
+        self.plugins.insert(name, plugin);
+        Ok(())
+    }
+
+    /// Loads the plugin libraries found in `plugins_dir` and the WebAssembly plugins.
+    /// Their subcommands and default configs are not available, since they are loaded after the config.
+    pub fn load(&mut self) -> Result<(), String> {
+        #[cfg(feature = "dynamic-plugins")]
+        if let Some(dir) = config!(plugins_dir) {
+            for dynamic in dynamic::load_dir(dir)? {
+                self.register(dynamic.plugin.name().to_string(), Loaded::Dynamic(dynamic))?;
+            }
+        }
+        #[cfg(feature = "wasm-plugins")]
+        if let Some(wasm) = config!(wasm_plugins) {
+            for plugin in wasm::load_dir(wasm)? {
+                self.register(plugin.name.clone(), Loaded::Wasm(plugin))?;
+            }
+        }
+        *PLUGIN_NAMES.write().expect("Plugin names lock was poisoned. This is a bug") = self.plugins.keys().cloned().collect();
+        *DISABLED.write().expect("Disabled plugins lock was poisoned. This is a bug") = config!(plugin_policies)
+            .iter()
+            .filter(|(_, policy)| !policy.enabled)
+            .map(|(name, _)| name.clone())
+            .collect();
+        Ok(())
     }
 
     pub fn add_subcmds<'a>(&self, mut cmd: CommandBuilder<&'a str>) -> CommandBuilder<&'a str> {
         for (_, plugin) in &self.plugins {
-            if let Some(subcmd) = plugin.subcmd() {
+            if let Some(subcmd) = plugin.plugin().and_then(|plugin| plugin.subcmd()) {
                 cmd = cmd.subcommand(subcmd);
             }
         }
@@ -56,7 +192,7 @@ impl Plugins {
 
     pub fn handle_args(&self, parsed: &ParsedCommand) -> bool {
         if !parsed.parents.is_empty() {
-            if let Some(plugin) = self.plugins.get(&parsed.name) {
+            if let Some(plugin) = self.plugins.get(&parsed.name).and_then(Loaded::plugin) {
                 plugin.handle_args(parsed);
                 return true;
             }
@@ -67,7 +203,7 @@ impl Plugins {
     pub fn default_configs(&self) -> Table {
         let mut table = Table::new();
         for (name, plugin) in &self.plugins {
-            if let Some(config) = plugin.config() {
+            if let Some(config) = plugin.plugin().and_then(|plugin| plugin.config()) {
                 table.insert(name.clone(), Toml::Table(config));
             }
         }
@@ -76,33 +212,184 @@ impl Plugins {
 
     pub fn init(&mut self, config: &Table) -> Result<(), String> {
         for (name, plugin) in &mut self.plugins {
-            plugin.init(config.get(name))?;
+            if let Some(plugin) = plugin.plugin_mut() {
+                plugin.init(config.get(name))?;
+            }
+            if let Some(ui) = plugin.hooks().and_then(|hooks| hooks.ui()) {
+                self.uis.insert(name.clone(), ui);
+            }
             log::info!("Plugin '{name}' initialized.");
         }
         Ok(())
     }
 
+    pub fn ui(&self, name: &str) -> Option<&PluginUi> {
+        self.uis.get(name)
+    }
+
+    /// Returns the web UI of every plugin the user can access, sorted by name
+    pub fn uis(&self, user: &Option<User>) -> Vec<(&str, &PluginUi)> {
+        let mut uis: Vec<_> = self
+            .uis
+            .iter()
+            .filter(|(name, _)| self.authorize(name, user).is_ok())
+            .map(|(name, ui)| (name.as_str(), ui))
+            .collect();
+        uis.sort_by_key(|(name, _)| *name);
+        uis
+    }
+
+    /// Returns the plugin if it exists, is enabled and its policy allows the user to access it
+    fn get(&self, name: &str, user: &Option<User>) -> Result<&Loaded, PluginError> {
+        let plugin = self.plugins.get(name).filter(|_| is_enabled(name)).ok_or(PluginError::NotFound)?;
+        match (access(name), user) {
+            (PluginAccess::Anonymous, _) => Ok(plugin),
+            (_, None) => Err(PluginError::AuthRequired),
+            (PluginAccess::Admin, Some(user)) if !user.is_admin => Err(PluginError::AdminRequired),
+            (PluginAccess::Auth | PluginAccess::Admin, Some(_)) => Ok(plugin),
+        }
+    }
+
+    /// Checks that the user can access the plugin
+    pub fn authorize(&self, name: &str, user: &Option<User>) -> Result<(), PluginError> {
+        self.get(name, user).map(|_| ())
+    }
+
+    /// Starts a chain of service calls made by `caller` on behalf of `user`
+    pub fn services<'a>(&'a self, caller: &str, user: &'a Option<User>) -> ServiceCall<'a> {
+        ServiceCall::new(self, caller, user)
+    }
+
     pub async fn request(&self, name: String, user: Option<User>, body: Json) -> HttpResponse {
         log::info!("Requested '{name}'");
-        if let Some(plugin) = self.plugins.get(&name) {
-            let path = plugin_path(&user, name);
-            plugin.request(user, body, path).await
-        } else {
-            HttpResponse::NotFound().body("")
-        }
+        let plugin = match self.get(&name, &user) {
+            Ok(plugin) => plugin,
+            Err(err) => return err.to_response(),
+        };
+        let path = plugin_path(&user, name.clone());
+        let start = Instant::now();
+        let res = plugin.request(user, body, path).await;
+        metrics::plugin(&name, "request", res.status().as_u16(), start.elapsed());
+        res
     }
 
     pub async fn file(&self, name: String, user: Option<User>, file: FileForm) -> HttpResponse {
-        if let Some(plugin) = self.plugins.get(&name) {
-            let path = plugin_path(&user, name);
-            plugin.file(user, file.file, file.info.into_inner(), path).await
-        } else {
-            HttpResponse::NotFound().body("")
+        let plugin = match self.get(&name, &user) {
+            Ok(plugin) => plugin,
+            Err(err) => return err.to_response(),
+        };
+        let path = plugin_path(&user, name.clone());
+        metrics::upload(&name, file.file.size);
+        let start = Instant::now();
+        let res = plugin.file(user, file, path).await;
+        metrics::plugin(&name, "file", res.status().as_u16(), start.elapsed());
+        res
+    }
+
+    /// Mounts the routes of every plugin under `/p/{plugin}`
+    pub fn routes(&self, cfg: &mut ServiceConfig) {
+        for (name, plugin) in &self.plugins {
+            if let Some(hooks) = plugin.hooks() {
+                let plugin = name.clone();
+                cfg.service(
+                    web::scope(&format!("/p/{name}"))
+                        .app_data(PluginName(name.clone()))
+                        .wrap(from_fn(move |req, next| api::plugins::authorize(plugin.clone(), req, next)))
+                        .configure(|cfg| hooks.routes(cfg)),
+                );
+            }
+        }
+    }
+
+    /// Returns the details of every plugin the user can access, sorted by name, without their status
+    pub fn info(&self, user: &Option<User>) -> Vec<PluginInfo> {
+        let mut names: Vec<_> = self.plugins.keys().filter(|name| self.authorize(name, user).is_ok()).collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let mut endpoints = vec![
+                    format!("POST {}", utils::make_url(&format!("/api/p/{name}"))),
+                    format!("POST {}", utils::make_url(&format!("/api/up/{name}"))),
+                ];
+                let metadata = self.plugins[name].hooks().map(|hooks| hooks.metadata()).unwrap_or_default();
+                endpoints.extend(
+                    metadata
+                        .routes
+                        .iter()
+                        .map(|(method, route)| format!("{method} {}", utils::make_url(&format!("/api/p/{name}{route}")))),
+                );
+                if self.uis.contains_key(name) {
+                    endpoints.push(format!("GET {}", utils::make_url(&format!("/ui/p/{name}"))));
+                }
+                PluginInfo {
+                    name: name.clone(),
+                    version: metadata.version,
+                    description: metadata.description,
+                    access: access(name),
+                    endpoints,
+                    status: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the health checks of the plugins in `info` and sets their status
+    pub async fn check_health(&self, info: &mut [PluginInfo]) {
+        for plugin in info {
+            let healthy = match self.plugins.get(&plugin.name).and_then(Loaded::hooks) {
+                Some(hooks) => hooks.health().await.is_ok(),
+                None => true,
+            };
+            plugin.status = Some(if healthy { "ok" } else { "unhealthy" });
+        }
+    }
+
+    /// Returns the health of every plugin
+    pub async fn health(&self) -> Vec<(&str, Result<(), String>)> {
+        let mut health = Vec::with_capacity(self.plugins.len());
+        for (name, plugin) in &self.plugins {
+            let result = match plugin.hooks() {
+                Some(hooks) => hooks.health().await,
+                None => Ok(()),
+            };
+            health.push((name.as_str(), result));
+        }
+        health
+    }
+
+    /// Returns the names of the enabled plugins subscribed to this event
+    pub fn subscribers(&self, event: &ServerEvent) -> Vec<String> {
+        self.plugins
+            .iter()
+            .filter(|(name, plugin)| is_enabled(name) && plugin.hooks().is_some_and(|hooks| hooks.subscribes(event)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Delivers an event to a plugin
+    pub async fn on_event(&self, name: &str, event: &ServerEvent) -> Result<(), String> {
+        match self.plugins.get(name).and_then(|plugin| plugin.hooks()) {
+            Some(hooks) => hooks.on_event(event).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Lets every plugin flush its state before the server exits
+    pub async fn shutdown(&self) {
+        for (name, plugin) in &self.plugins {
+            let Some(hooks) = plugin.hooks() else {
+                continue;
+            };
+            match hooks.shutdown().await {
+                Ok(()) => log::info!("Plugin '{name}' shut down."),
+                Err(e) => log::error!("Failed to shut down plugin '{name}': {e}"),
+            }
         }
     }
 }
 
-fn plugin_path(user: &Option<User>, plugin: String) -> PathBuf {
+pub fn plugin_path(user: &Option<User>, plugin: String) -> PathBuf {
     let mut path = PathBuf::from(config!(data_directory));
     match user {
         Some(user) => {
@@ -118,8 +405,36 @@ fn plugin_path(user: &Option<User>, plugin: String) -> PathBuf {
     path
 }
 
-pub fn list() -> &'static Vec<String> {
-    PLUGIN_NAMES
-        .get()
-        .expect("Tried to access PLUGIN_NAMES when this value was not initialized. This is a bug")
+pub fn is_enabled(name: &str) -> bool {
+    !DISABLED
+        .read()
+        .expect("Disabled plugins lock was poisoned. This is a bug")
+        .iter()
+        .any(|disabled| disabled == name)
+}
+
+/// Enables or disables a plugin until the server restarts
+pub fn set_enabled(name: &str, enabled: bool) {
+    let mut disabled = DISABLED.write().expect("Disabled plugins lock was poisoned. This is a bug");
+    disabled.retain(|disabled| disabled != name);
+    if !enabled {
+        disabled.push(name.into());
+    }
+}
+
+/// Returns who can use the plugin according to its policy
+pub fn access(name: &str) -> PluginAccess {
+    config!(plugin_policies)
+        .get(name)
+        .map_or(PluginAccess::Anonymous, |policy| policy.access)
+}
+
+/// Returns every plugin, even the disabled ones
+pub fn list_all() -> Vec<String> {
+    PLUGIN_NAMES.read().expect("Plugin names lock was poisoned. This is a bug").clone()
+}
+
+/// Returns the enabled plugins
+pub fn list() -> Vec<String> {
+    list_all().into_iter().filter(|name| is_enabled(name)).collect()
 }
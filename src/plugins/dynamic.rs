@@ -0,0 +1,111 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use libloading::Library;
+use std::{env::consts::DLL_EXTENSION, fs, path::Path};
+use tcloud_library::plugin::Plugin;
+
+/// Version of [`Declaration`], increased whenever its layout or meaning changes
+pub const ABI_VERSION: u32 = 1;
+/// Compiler used to build the server. Trait objects can only be shared with code built by the same one.
+pub const RUSTC_VERSION: &str = env!("TCLOUD_RUSTC_VERSION");
+/// Version of `tcloud-library` the server was built with
+pub const LIBRARY_VERSION: &str = env!("TCLOUD_LIBRARY_VERSION");
+
+/// Symbol exported by every plugin library
+const SYMBOL: &[u8] = b"TCLOUD_PLUGIN\0";
+
+/// Exported by every plugin library as a static named `TCLOUD_PLUGIN`,
+/// built with the same compiler and `tcloud-library` version as the server:
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub static TCLOUD_PLUGIN: Declaration = Declaration {
+///     abi_version: 1,
+///     rustc_version: "rustc 1.xx.x (...)",
+///     library_version: "0.0.1",
+///     create: || Box::new(MyPlugin::new()),
+/// };
+/// ```
+#[repr(C)]
+pub struct Declaration {
+    /// Checked before anything else is read
+    pub abi_version: u32,
+    pub rustc_version: &'static str,
+    pub library_version: &'static str,
+    pub create: fn() -> Box<dyn Plugin>,
+}
+
+/// A plugin loaded from a shared library.
+/// The plugin is declared first so that it is dropped before its library is unloaded.
+pub struct Dynamic {
+    pub plugin: Box<dyn Plugin>,
+    _library: Library,
+}
+
+/// Loads a single plugin library, checking that it is compatible with the server
+fn load(path: &Path) -> Result<Dynamic, String> {
+    let display = path.display();
+    // SAFETY: the library is trusted to export a `Declaration` as documented above.
+    // Its ABI version is checked before the rest of the declaration is read,
+    // and the compiler and library versions before the plugin is created.
+    unsafe {
+        let library = Library::new(path).map_err(|e| format!("Failed to load plugin `{display}`: {e}"))?;
+        let declaration: &Declaration = &**library
+            .get::<*const Declaration>(SYMBOL)
+            .map_err(|e| format!("`{display}` is not a plugin, `TCLOUD_PLUGIN` not found: {e}"))?;
+        if declaration.abi_version != ABI_VERSION {
+            return Err(format!(
+                "Plugin `{display}` uses ABI version {}, but this server supports version {ABI_VERSION}",
+                declaration.abi_version
+            ));
+        }
+        if declaration.rustc_version != RUSTC_VERSION {
+            return Err(format!(
+                "Plugin `{display}` was built with `{}`, but this server was built with `{RUSTC_VERSION}`",
+                declaration.rustc_version
+            ));
+        }
+        if declaration.library_version != LIBRARY_VERSION {
+            return Err(format!(
+                "Plugin `{display}` uses tcloud-library {}, but this server uses {LIBRARY_VERSION}",
+                declaration.library_version
+            ));
+        }
+        Ok(Dynamic {
+            plugin: (declaration.create)(),
+            _library: library,
+        })
+    }
+}
+
+/// Loads every plugin library in `dir`
+pub fn load_dir(dir: &str) -> Result<Vec<Dynamic>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read plugins directory `{dir}`: {e}"))?;
+    let mut plugins = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Failed to read plugins directory `{dir}`: {e}"))?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == DLL_EXTENSION) {
+            plugins.push(load(&path)?);
+        }
+    }
+    Ok(plugins)
+}
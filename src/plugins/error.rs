@@ -27,6 +27,26 @@ use thiserror::Error;
 pub enum PluginError {
     #[error("An internal server error occurred.")]
     InternalError(String),
+    #[error("File not found")]
+    FileNotFound,
+    #[error("Invalid file path")]
+    InvalidPath,
+    #[error("Plugin not found")]
+    NotFound,
+    #[error("Login to use this plugin")]
+    AuthRequired,
+    #[error("Only admins can use this plugin")]
+    AdminRequired,
+    #[error("Service not found")]
+    ServiceNotFound,
+    #[error("Service call cycle: {0}")]
+    ServiceCycle(String),
+    #[error("Too many nested service calls: {0}")]
+    ServiceTooDeep(String),
+    #[error("Service failed: {0}")]
+    ServiceFailed(String),
+    #[error("Plugin storage quota exceeded")]
+    QuotaExceeded,
 }
 
 impl ErrToResponse for PluginError {
@@ -37,6 +57,16 @@ impl ErrToResponse for PluginError {
     fn err_type(&self) -> &'static str {
         match self {
             Self::InternalError(_) => stringify!(InternalError),
+            Self::FileNotFound => stringify!(FileNotFound),
+            Self::InvalidPath => stringify!(InvalidPath),
+            Self::NotFound => stringify!(NotFound),
+            Self::AuthRequired => stringify!(AuthRequired),
+            Self::AdminRequired => stringify!(AdminRequired),
+            Self::ServiceNotFound => stringify!(ServiceNotFound),
+            Self::ServiceCycle(_) => stringify!(ServiceCycle),
+            Self::ServiceTooDeep(_) => stringify!(ServiceTooDeep),
+            Self::ServiceFailed(_) => stringify!(ServiceFailed),
+            Self::QuotaExceeded => stringify!(QuotaExceeded),
         }
     }
 
@@ -47,11 +77,28 @@ impl ErrToResponse for PluginError {
     fn http_code(&self) -> HttpResponseBuilder {
         match self {
             Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::FileNotFound => HttpResponse::NotFound(),
+            Self::InvalidPath => HttpResponse::BadRequest(),
+            Self::NotFound => HttpResponse::NotFound(),
+            Self::AuthRequired => HttpResponse::Unauthorized(),
+            Self::AdminRequired => HttpResponse::Forbidden(),
+            Self::ServiceNotFound => HttpResponse::NotFound(),
+            Self::ServiceCycle(_) => HttpResponse::LoopDetected(),
+            Self::ServiceTooDeep(_) => HttpResponse::InternalServerError(),
+            Self::ServiceFailed(_) => HttpResponse::BadGateway(),
+            Self::QuotaExceeded => HttpResponse::InsufficientStorage(),
         }
     }
 
     fn handle(&self) {
-        let Self::InternalError(err) = self;
-        log::error!("An internal server error occurred during authentication: {err}");
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred during authentication: {err}");
+        }
+        if let Self::ServiceCycle(chain) = self {
+            log::error!("Plugins called each other in a cycle: {chain}");
+        }
+        if let Self::ServiceTooDeep(chain) = self {
+            log::error!("Plugins nested too many service calls: {chain}");
+        }
     }
 }
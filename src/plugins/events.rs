@@ -0,0 +1,119 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::Plugins;
+use actix_web::{rt, web::Data};
+use std::sync::Mutex;
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+
+/// Queue of the emitted events and the task delivering them
+struct Bus {
+    sender: UnboundedSender<ServerEvent>,
+    task: JoinHandle<()>,
+}
+
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+/// Events broadcast to the plugins that subscribe to them
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    UserCreated {
+        user: String,
+    },
+    UserDeleted {
+        user: String,
+    },
+    Login {
+        user: String,
+    },
+    Logout {
+        user: String,
+    },
+    /// A registration token was used by `user` to register
+    TokenConsumed {
+        user: String,
+    },
+    /// Delivered before [`Hooks::shutdown`](super::hooks::Hooks::shutdown), after every request has been handled
+    Shutdown,
+}
+
+/// Queues an event for delivery. Does nothing while the server is not running, e.g. from the CLI.
+pub fn emit(event: ServerEvent) {
+    if let Some(bus) = BUS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if bus.sender.send(event).is_err() {
+            log::warn!("Event bus is closed, event dropped");
+        }
+    }
+}
+
+/// Delivers an event to every subscribed plugin, each in its own task,
+/// so that a plugin failing or panicking does not affect the others
+async fn broadcast(plugins: &Data<Plugins>, event: ServerEvent) {
+    let tasks: Vec<_> = plugins
+        .subscribers(&event)
+        .into_iter()
+        .map(|name| {
+            let plugins = Data::clone(plugins);
+            let event = event.clone();
+            let task_name = name.clone();
+            (name, rt::spawn(async move { plugins.on_event(&task_name, &event).await }))
+        })
+        .collect();
+    for (name, task) in tasks {
+        match task.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => log::error!("Plugin '{name}' failed to handle event: {e}"),
+            Err(e) => log::error!("Plugin '{name}' panicked while handling event: {e}"),
+        }
+    }
+}
+
+/// Starts delivering the emitted events to the plugins, one event at a time in the order they were emitted
+pub fn start(plugins: Data<Plugins>) {
+    let mut bus = BUS.lock().unwrap_or_else(|e| e.into_inner());
+    if bus.is_some() {
+        log::error!("Event bus was already started. This is a bug");
+        return;
+    }
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let task = rt::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            broadcast(&plugins, event).await;
+        }
+    });
+    *bus = Some(Bus { sender, task });
+}
+
+/// Stops accepting events, delivers the queued ones and then [`ServerEvent::Shutdown`]
+pub async fn stop(plugins: &Data<Plugins>) {
+    let bus = BUS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(Bus { sender, task }) = bus {
+        // Closes the queue, the task ends once it is empty
+        drop(sender);
+        if let Err(e) = task.await {
+            log::error!("Event bus task failed: {e}");
+        }
+    }
+    broadcast(plugins, ServerEvent::Shutdown).await;
+}
@@ -0,0 +1,121 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::{
+    events::ServerEvent,
+    services::{ServiceCall, ServiceRequest, ServiceResponse},
+};
+use actix_web::web::ServiceConfig;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tcloud_library::plugin::Plugin;
+
+/// Static file shipped with a plugin's web UI
+pub struct Asset {
+    pub content_type: &'static str,
+    pub data: &'static [u8],
+}
+
+/// Entry point of a plugin's web UI
+pub enum UiEntry {
+    /// HTML fragment placed in the body of the plugin's page
+    Html(String),
+    /// Name of the asset loaded as a JS module by the plugin's page
+    Module(&'static str),
+}
+
+/// Web UI of a plugin, served under `/ui/p/{plugin}`
+pub struct PluginUi {
+    /// Name shown in the home page
+    pub title: String,
+    pub entry: UiEntry,
+    /// Shown in the home page, served under `/ui/p/{plugin}/icon`
+    pub icon: Option<Asset>,
+    /// Served under `/ui/p/{plugin}/assets/{name}`
+    pub assets: HashMap<&'static str, Asset>,
+}
+
+/// Plugin details shown to clients in `/api/plugins`
+#[derive(Default)]
+pub struct Metadata {
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Methods and paths of the routes registered by [`Hooks::routes`], relative to `/api/p/{plugin}`,
+    /// e.g. `("GET", "/items/{id}")`
+    pub routes: Vec<(&'static str, &'static str)>,
+}
+
+/// Server hooks a plugin can optionally implement on top of [`Plugin`].
+/// Every hook has a default implementation that does nothing.
+#[async_trait]
+pub trait Hooks: Plugin {
+    /// Called once when the server is shutting down, after every request has been handled.
+    /// Plugins should flush their state here.
+    async fn shutdown(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called by the readiness check. Returns an error describing the problem if the plugin
+    /// cannot currently handle requests.
+    async fn health(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Registers routes served under `/api/p/{plugin}/...`, with any method, path pattern or body.
+    /// Handlers can extract a [`PluginContext`](crate::api::plugins::PluginContext)
+    /// to get the user making the request and its data path, which also enforces the plugin's policy.
+    fn routes(&self, _cfg: &mut ServiceConfig) {}
+
+    /// Returns the plugin's web UI. Called once, after the plugin is initialized.
+    fn ui(&self) -> Option<PluginUi> {
+        None
+    }
+
+    /// Returns the plugin's details shown to clients
+    fn metadata(&self) -> Metadata {
+        Metadata::default()
+    }
+
+    /// Returns true if the plugin wants to receive this event through [`Hooks::on_event`].
+    fn subscribes(&self, _event: &ServerEvent) -> bool {
+        false
+    }
+
+    /// Called asynchronously for every event the plugin subscribes to.
+    /// Errors are logged and do not affect the server or other plugins.
+    async fn on_event(&self, _event: &ServerEvent) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Names of the services other plugins can call through [`Hooks::call_service`]
+    fn services(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Handles a call to one of the plugin's services. `call` carries the user and the calling plugin,
+    /// see [`ServiceCall::caller`], and can be used to call other plugins' services in turn.
+    async fn call_service(&self, _call: &ServiceCall<'_>, request: ServiceRequest) -> Result<ServiceResponse, String> {
+        Err(format!("Unknown service '{}'", request.service))
+    }
+}
+
+#[cfg(feature = "archive")]
+impl Hooks for tcloud_archive::ArchivePlugin {}
@@ -0,0 +1,89 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::error::PluginError;
+use crate::{config, database::kv};
+use async_sqlite::Pool;
+use tcloud_library::plugin::User;
+
+pub use kv::KvOp;
+
+/// Key-value storage of a plugin for a single user, kept in the server's database.
+/// Keys and values count towards the `plugin_storage_bytes` limit and are deleted along with the user.
+/// Requests without a user have no storage.
+#[derive(Clone)]
+pub struct KvStore {
+    pool: Pool,
+    plugin: String,
+    user: String,
+}
+
+impl KvStore {
+    pub fn new(pool: Pool, plugin: String, user: &User) -> Self {
+        Self {
+            pool,
+            plugin,
+            user: user.name.clone(),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PluginError> {
+        kv::get(&self.pool, self.plugin.clone(), self.user.clone(), key.into())
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn set(&self, key: &str, value: Vec<u8>) -> Result<(), PluginError> {
+        self.transaction(vec![KvOp::Set(key.into(), value)]).await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), PluginError> {
+        self.transaction(vec![KvOp::Delete(key.into())]).await
+    }
+
+    /// Returns the keys starting with `prefix`, sorted
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>, PluginError> {
+        kv::list(&self.pool, self.plugin.clone(), self.user.clone(), prefix.into())
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Returns the bytes used by the keys and values
+    pub async fn usage(&self) -> Result<u64, PluginError> {
+        kv::usage(&self.pool, self.plugin.clone(), self.user.clone())
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Applies every change or none of them. Fails with [`PluginError::QuotaExceeded`]
+    /// if the storage would grow over `plugin_storage_bytes`.
+    pub async fn transaction(&self, ops: Vec<KvOp>) -> Result<(), PluginError> {
+        kv::apply(
+            &self.pool,
+            self.plugin.clone(),
+            self.user.clone(),
+            ops,
+            config!(limits).plugin_storage_bytes,
+        )
+        .await
+        .map_err(|e| e.into())
+    }
+}
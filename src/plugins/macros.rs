@@ -21,14 +21,14 @@
 
 /// Returns a new plugin instance.
 /// Requires the feature's name and the plugin's specific type.
-/// The plugin must implement a `new() -> Self` function.
+/// The plugin must implement a `new() -> Self` function and [`Hooks`](super::hooks::Hooks).
 #[macro_export]
 macro_rules! plugin {
     ($feature:literal, $plugin:ty) => {
         #[cfg(feature = $feature)]
         {
             let plugin = <$plugin>::new();
-            (plugin.name().into(), Box::new(plugin) as Box<dyn Plugin>)
+            (plugin.name().into(), Loaded::Builtin(Box::new(plugin)))
         }
     };
 }
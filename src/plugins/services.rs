@@ -0,0 +1,174 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use super::{error::PluginError, Plugins};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tcloud_library::{plugin::User, serde_json::Value};
+
+/// Maximum number of nested service calls
+const MAX_DEPTH: usize = 8;
+
+/// Request sent to a plugin's service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceRequest {
+    pub service: String,
+    pub body: Value,
+}
+
+impl ServiceRequest {
+    pub fn new<T: Serialize>(service: &str, body: &T) -> Result<Self, String> {
+        Ok(Self {
+            service: service.into(),
+            body: tcloud_library::serde_json::to_value(body).map_err(|e| format!("Failed to serialize request: {e}"))?,
+        })
+    }
+
+    /// Deserializes the body into the type expected by the service
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, String> {
+        T::deserialize(&self.body).map_err(|e| format!("Invalid request for service '{}': {e}", self.service))
+    }
+}
+
+/// Response returned by a plugin's service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceResponse {
+    pub body: Value,
+}
+
+impl ServiceResponse {
+    pub fn new<T: Serialize>(body: &T) -> Result<Self, String> {
+        Ok(Self {
+            body: tcloud_library::serde_json::to_value(body).map_err(|e| format!("Failed to serialize response: {e}"))?,
+        })
+    }
+
+    /// Deserializes the body into the type returned by the service
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, String> {
+        T::deserialize(&self.body).map_err(|e| format!("Invalid service response: {e}"))
+    }
+}
+
+/// Handle used by a plugin to call other plugins' services on behalf of a user.
+/// Every call is checked against the target plugin's policy with the same user.
+pub struct ServiceCall<'a> {
+    plugins: &'a Plugins,
+    user: &'a Option<User>,
+    /// Plugins currently in the call chain, the first one started it
+    chain: Vec<String>,
+}
+
+impl<'a> ServiceCall<'a> {
+    pub(super) fn new(plugins: &'a Plugins, caller: &str, user: &'a Option<User>) -> Self {
+        Self {
+            plugins,
+            user,
+            chain: vec![caller.into()],
+        }
+    }
+
+    /// User on whose behalf the services are called
+    pub fn user(&self) -> &Option<User> {
+        self.user
+    }
+
+    /// Plugin that called the service being handled, or [`None`] for the plugin that started the chain
+    pub fn caller(&self) -> Option<&str> {
+        self.chain.len().checked_sub(2).map(|i| self.chain[i].as_str())
+    }
+
+    /// Returns the handle passed to `plugin` when it is called by the current one
+    fn nested(&self, plugin: &str) -> Result<Self, PluginError> {
+        let chain = || format!("{} -> {plugin}", self.chain.join(" -> "));
+        if self.chain.iter().any(|name| name == plugin) {
+            return Err(PluginError::ServiceCycle(chain()));
+        }
+        if self.chain.len() >= MAX_DEPTH {
+            return Err(PluginError::ServiceTooDeep(chain()));
+        }
+        let mut nested = self.chain.clone();
+        nested.push(plugin.into());
+        Ok(Self {
+            plugins: self.plugins,
+            user: self.user,
+            chain: nested,
+        })
+    }
+
+    /// Calls a service of another plugin.
+    /// Fails if the plugin is already in the call chain or the chain would grow beyond 8 plugins.
+    pub async fn call(&self, plugin: &str, request: ServiceRequest) -> Result<ServiceResponse, PluginError> {
+        let nested = self.nested(plugin)?;
+        let hooks = self
+            .plugins
+            .get(plugin, self.user)?
+            .hooks()
+            .filter(|hooks| hooks.services().contains(&request.service.as_str()))
+            .ok_or(PluginError::ServiceNotFound)?;
+        let current = self.chain.last().map_or("", |name| name.as_str());
+        log::debug!("Plugin '{current}' calls service '{}' of '{plugin}'", request.service);
+        hooks.call_service(&nested, request).await.map_err(PluginError::ServiceFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn plugins() -> Plugins {
+        Plugins {
+            plugins: HashMap::new(),
+            uis: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn tracks_caller() {
+        let plugins = plugins();
+        let root = ServiceCall::new(&plugins, "a", &None);
+        assert_eq!(root.caller(), None);
+        let b = root.nested("b").unwrap();
+        assert_eq!(b.caller(), Some("a"));
+        assert_eq!(b.nested("c").unwrap().caller(), Some("b"));
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let plugins = plugins();
+        let root = ServiceCall::new(&plugins, "a", &None);
+        assert!(matches!(root.nested("a"), Err(PluginError::ServiceCycle(_))));
+        let c = root.nested("b").unwrap().nested("c").unwrap();
+        match c.nested("a") {
+            Err(PluginError::ServiceCycle(chain)) => assert_eq!(chain, "a -> b -> c -> a"),
+            _ => panic!("cycle not detected"),
+        }
+    }
+
+    #[test]
+    fn limits_depth() {
+        let plugins = plugins();
+        let mut call = ServiceCall::new(&plugins, "p0", &None);
+        for i in 1..MAX_DEPTH {
+            call = call.nested(&format!("p{i}")).unwrap();
+        }
+        assert!(matches!(call.nested("last"), Err(PluginError::ServiceTooDeep(_))));
+    }
+}
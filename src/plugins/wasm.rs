@@ -0,0 +1,259 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::config;
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::{http::StatusCode, HttpResponse};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tcloud_library::{
+    plugin::User,
+    serde_json::{json, Value},
+    Json,
+};
+use wasmtime::{Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::{
+    preview1::{self, WasiP1Ctx},
+    DirPerms, FilePerms, WasiCtxBuilder,
+};
+
+/// Where the plugin's data directory is mounted inside the sandbox
+const GUEST_DATA_DIR: &str = "/data";
+/// Prefix of the name given to an uploaded file inside the data directory while the plugin handles it
+const UPLOAD_PREFIX: &str = ".upload-";
+
+/// State of a single plugin call
+struct State {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    /// JSON given to the plugin through `tcloud.request_read`
+    request: Vec<u8>,
+    /// Status and JSON body set by the plugin through `tcloud.respond`
+    response: Option<(u32, Vec<u8>)>,
+}
+
+fn memory(caller: &mut Caller<'_, State>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("plugin does not export its memory"))
+}
+
+/// Host API imported by plugins from the `tcloud` module
+fn host_api(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    linker.func_wrap("tcloud", "request_len", |caller: Caller<'_, State>| {
+        caller.data().request.len() as u32
+    })?;
+    linker.func_wrap("tcloud", "request_read", |mut caller: Caller<'_, State>, ptr: u32| {
+        let memory = memory(&mut caller)?;
+        let (data, state) = memory.data_and_store_mut(&mut caller);
+        data.get_mut(ptr as usize..)
+            .and_then(|data| data.get_mut(..state.request.len()))
+            .ok_or_else(|| wasmtime::Error::msg("request buffer out of bounds"))?
+            .copy_from_slice(&state.request);
+        Ok(())
+    })?;
+    linker.func_wrap(
+        "tcloud",
+        "respond",
+        |mut caller: Caller<'_, State>, status: u32, ptr: u32, len: u32| {
+            let memory = memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let body = data
+                .get(ptr as usize..)
+                .and_then(|data| data.get(..len as usize))
+                .ok_or_else(|| wasmtime::Error::msg("response buffer out of bounds"))?;
+            state.response = Some((status, body.to_vec()));
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+/// Compiled plugin, shared by every call
+struct Runtime {
+    engine: Engine,
+    module: Module,
+    linker: Linker<State>,
+}
+
+impl Runtime {
+    /// Runs the plugin's `handle` export in a new sandbox that can only access `path`
+    fn call(&self, request: Vec<u8>, path: PathBuf) -> wasmtime::Result<(u32, Vec<u8>)> {
+        let limits = config!(wasm_plugins)
+            .as_ref()
+            .expect("WASM plugin without WASM config. This is a bug");
+        let mut wasi = WasiCtxBuilder::new();
+        wasi.preopened_dir(&path, GUEST_DATA_DIR, DirPerms::all(), FilePerms::all())?;
+        let state = State {
+            wasi: wasi.build_p1(),
+            limits: StoreLimitsBuilder::new().memory_size(limits.max_memory_bytes).instances(1).build(),
+            request,
+            response: None,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(limits.fuel)?;
+        let instance = self.linker.instantiate(&mut store, &self.module)?;
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            init.call(&mut store, ())?;
+        }
+        instance.get_typed_func::<(), ()>(&mut store, "handle")?.call(&mut store, ())?;
+        store
+            .into_data()
+            .response
+            .ok_or_else(|| wasmtime::Error::msg("plugin did not respond"))
+    }
+}
+
+/// A plugin compiled to WebAssembly, which runs in a WASI sandbox limited to its data directory.
+/// Every call instantiates the module again, so no state is kept in memory between requests.
+///
+/// The module must export `handle()`, which reads the request with the imported
+/// `tcloud.request_len() -> u32` and `tcloud.request_read(ptr: u32)` and answers with
+/// `tcloud.respond(status: u32, ptr: u32, len: u32)`. The request is a JSON object with
+/// the `user` (or `null`), the `body` and, for uploads, the `file` path inside the sandbox.
+pub struct WasmPlugin {
+    pub name: String,
+    runtime: Arc<Runtime>,
+}
+
+impl WasmPlugin {
+    async fn call(&self, request: Value, path: PathBuf) -> HttpResponse {
+        if let Err(e) = self.create_dir(&path).await {
+            return e;
+        }
+        let runtime = Arc::clone(&self.runtime);
+        let request = request.to_string().into_bytes();
+        match tokio::task::spawn_blocking(move || runtime.call(request, path)).await {
+            Ok(Ok((status, body))) => match u16::try_from(status).ok().and_then(|status| StatusCode::from_u16(status).ok()) {
+                Some(status) => HttpResponse::build(status).content_type("application/json").body(body),
+                None => {
+                    log::error!("WASM plugin '{}' responded with invalid status {status}", self.name);
+                    HttpResponse::InternalServerError().body("")
+                }
+            },
+            Ok(Err(e)) => {
+                log::error!("WASM plugin '{}' failed: {e:#}", self.name);
+                HttpResponse::InternalServerError().body("")
+            }
+            Err(e) => {
+                log::error!("WASM plugin '{}' task failed: {e}", self.name);
+                HttpResponse::InternalServerError().body("")
+            }
+        }
+    }
+
+    /// Creates the data directory, which does not exist yet for users created before the plugin was added
+    async fn create_dir(&self, path: &Path) -> Result<(), HttpResponse> {
+        tokio::fs::create_dir_all(path).await.map_err(|e| {
+            log::error!("Failed to create data directory of WASM plugin '{}': {e}", self.name);
+            HttpResponse::InternalServerError().body("")
+        })
+    }
+
+    fn user(user: &Option<User>) -> Value {
+        match user {
+            Some(user) => json!({ "name": user.name, "is_admin": user.is_admin }),
+            None => Value::Null,
+        }
+    }
+
+    pub async fn request(&self, user: Option<User>, body: Json, path: PathBuf) -> HttpResponse {
+        let request = json!({ "user": Self::user(&user), "body": body, "file": null });
+        self.call(request, path).await
+    }
+
+    /// Moves the upload into the plugin's data directory while the plugin handles it
+    pub async fn file(&self, user: Option<User>, file: TempFile, info: Json, path: PathBuf) -> HttpResponse {
+        let upload_name = format!(
+            "{UPLOAD_PREFIX}{}",
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect::<String>()
+        );
+        let upload = path.join(&upload_name);
+        if let Err(e) = self.create_dir(&path).await {
+            return e;
+        }
+        // Renamed when possible, copied only if the temporary file is on another filesystem
+        if let Err(e) = file.file.persist(&upload) {
+            if let Err(e) = tokio::fs::copy(e.file.path(), &upload).await {
+                log::error!("Failed to move upload for WASM plugin '{}': {e}", self.name);
+                return HttpResponse::InternalServerError().body("");
+            }
+        }
+        let request = json!({
+            "user": Self::user(&user),
+            "body": info,
+            "file": format!("{GUEST_DATA_DIR}/{upload_name}"),
+        });
+        let res = self.call(request, path).await;
+        if let Err(e) = tokio::fs::remove_file(&upload).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::error!("Failed to remove upload of WASM plugin '{}': {e}", self.name);
+            }
+        }
+        res
+    }
+}
+
+/// Compiles every `.wasm` module in the configured directory, naming each plugin after its file
+pub fn load_dir(wasm: &config::Wasm) -> Result<Vec<WasmPlugin>, String> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(|e| format!("Failed to create WASM engine: {e}"))?;
+    let mut linker = Linker::new(&engine);
+    preview1::add_to_linker_sync(&mut linker, |state: &mut State| &mut state.wasi).map_err(|e| format!("Failed to link WASI: {e}"))?;
+    host_api(&mut linker).map_err(|e| format!("Failed to link host API: {e}"))?;
+
+    let dir = &wasm.dir;
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read WASM plugins directory `{dir}`: {e}"))?;
+    let mut plugins = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read WASM plugins directory `{dir}`: {e}"))?
+            .path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "wasm") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            return Err(format!("Invalid WASM plugin name `{}`", path.display()));
+        };
+        let module = Module::from_file(&engine, &path).map_err(|e| format!("Failed to compile `{}`: {e}", path.display()))?;
+        let runtime = Runtime {
+            engine: engine.clone(),
+            module,
+            linker: linker.clone(),
+        };
+        plugins.push(WasmPlugin {
+            name: name.to_string(),
+            runtime: Arc::new(runtime),
+        });
+    }
+    Ok(plugins)
+}
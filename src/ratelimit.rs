@@ -0,0 +1,299 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{
+    config::{self, RateLimit},
+    error::RequestError,
+    utils,
+};
+use actix_identity::IdentityExt;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderValue, RETRY_AFTER},
+    middleware::Next,
+    rt, Error,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tcloud_library::error::ErrToResponse;
+
+type Buckets = HashMap<(String, String), Bucket>;
+
+/// Buckets of every limit and client, keyed by limit name and client
+static BUCKETS: OnceLock<Mutex<Buckets>> = OnceLock::new();
+
+/// Once there are this many buckets, new clients share one bucket per limit until the next sweep
+const MAX_BUCKETS: usize = 10_000;
+
+/// Client of the bucket shared by new clients when there are too many buckets
+const OVERFLOW_CLIENT: &str = "overflow";
+
+/// How often the full buckets are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst.into(),
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit) {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.updated).as_secs_f64() * f64::from(limit.per_minute) / 60.0;
+        self.tokens = (self.tokens + refilled).min(limit.burst.into());
+        self.updated = now;
+    }
+
+    /// Takes a token, or returns how long it takes for the next one to be available
+    fn take(&mut self, limit: &RateLimit) -> Result<(), Duration> {
+        self.refill(limit);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / f64::from(limit.per_minute)))
+        }
+    }
+}
+
+/// Checks the rate limits config, so that no limit can block a client forever
+pub fn check(limits: &config::RateLimits) -> Result<(), String> {
+    let global = limits.global.iter().map(|limit| ("global", limit));
+    let named = limits
+        .groups
+        .iter()
+        .chain(&limits.plugins)
+        .map(|(name, limit)| (name.as_str(), limit));
+    for (name, limit) in global.chain(named) {
+        if limit.burst == 0 || limit.per_minute == 0 {
+            return Err(format!("Rate limit `{name}` must allow at least one request"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the limit with the given name: `global`, a route group or `p/` followed by a plugin's name
+fn limit_named<'a>(limits: &'a config::RateLimits, name: &str) -> Option<&'a RateLimit> {
+    match name.strip_prefix("p/") {
+        Some(plugin) => limits.plugins.get(plugin),
+        None if name == "global" => limits.global.as_ref(),
+        None => limits.groups.get(name),
+    }
+}
+
+/// Returns the most specific limit that applies to a route and its name
+fn find_limit<'a>(limits: &'a config::RateLimits, group: &str, plugin: Option<&str>) -> Option<(String, &'a RateLimit)> {
+    let names = [
+        plugin.map(|plugin| format!("p/{plugin}")),
+        Some(group.into()),
+        Some("global".into()),
+    ];
+    names
+        .into_iter()
+        .flatten()
+        .find_map(|name| limit_named(limits, &name).map(|limit| (name, limit)))
+}
+
+/// Takes a token from the client's bucket. Runs in constant time, even when there are too many buckets.
+fn take_token(buckets: &mut Buckets, name: &str, client: String, limit: &RateLimit) -> Result<(), Duration> {
+    let mut key = (name.to_string(), client);
+    if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+        key.1 = OVERFLOW_CLIENT.into();
+    }
+    buckets.entry(key).or_insert_with(|| Bucket::new(limit)).take(limit)
+}
+
+/// Drops the buckets that are full again, which are the same as new ones
+fn sweep(buckets: &mut Buckets, limits: &config::RateLimits) {
+    buckets.retain(|(name, _), bucket| {
+        limit_named(limits, name).is_some_and(|limit| {
+            bucket.refill(limit);
+            bucket.tokens < f64::from(limit.burst)
+        })
+    });
+}
+
+/// Periodically drops the full buckets
+pub fn start_sweeper() {
+    let Some(limits) = config!(rate_limit) else {
+        return;
+    };
+    rt::spawn(async move {
+        let mut timer = rt::time::interval(SWEEP_INTERVAL);
+        loop {
+            timer.tick().await;
+            if let Some(buckets) = BUCKETS.get() {
+                sweep(&mut buckets.lock().expect("Rate limiter lock was poisoned. This is a bug"), limits);
+            }
+        }
+    });
+}
+
+/// Returns the authenticated user, if any
+fn user(req: &ServiceRequest) -> Option<String> {
+    if let Some(user) = req.get_identity().ok().and_then(|id| id.id().ok()) {
+        return Some(user);
+    }
+    #[cfg(not(feature = "no-tls"))]
+    if let Some(user) = crate::tls::cert_user(req.request()) {
+        return Some(user.clone());
+    }
+    None
+}
+
+/// Limits the requests of every client with token buckets.
+/// Authenticated clients are limited per user, the others per IP.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(limits) = config!(rate_limit) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let (group, plugin) = utils::route_group(req.path());
+    let Some((name, limit)) = find_limit(limits, group, plugin.as_deref()) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let ip = utils::get_ip(req.request());
+    let user = user(&req);
+    let exempt_ip = ip
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| limits.exempt_ips.iter().any(|net| net.contains(&ip)));
+    if exempt_ip || user.as_ref().is_some_and(|user| limits.exempt_users.contains(user)) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let client = user.map_or_else(|| format!("ip:{ip}"), |user| format!("user:{user}"));
+
+    let result = take_token(
+        &mut BUCKETS
+            .get_or_init(Default::default)
+            .lock()
+            .expect("Rate limiter lock was poisoned. This is a bug"),
+        &name,
+        client,
+        limit,
+    );
+
+    match result {
+        Ok(()) => Ok(next.call(req).await?.map_into_left_body()),
+        Err(wait) => {
+            log::warn!("client [{ip}] exceeded the `{name}` rate limit");
+            let mut res = RequestError::RateLimited.to_response();
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+            if let Ok(value) = HeaderValue::from_str(&retry_after.to_string()) {
+                res.headers_mut().insert(RETRY_AFTER, value);
+            }
+            Ok(req.into_response(res).map_into_right_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit { burst: 2, per_minute: 1 };
+
+    fn limits() -> config::RateLimits {
+        config::RateLimits {
+            global: Some(LIMIT),
+            groups: HashMap::from([("plugins".into(), LIMIT)]),
+            plugins: HashMap::from([("files".into(), LIMIT)]),
+            exempt_ips: Vec::new(),
+            exempt_users: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_waits() {
+        let mut bucket = Bucket::new(&LIMIT);
+        assert!(bucket.take(&LIMIT).is_ok());
+        assert!(bucket.take(&LIMIT).is_ok());
+        let wait = bucket.take(&LIMIT).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = Bucket::new(&LIMIT);
+        bucket.tokens = 0.0;
+        bucket.updated -= Duration::from_secs(60);
+        assert!(bucket.take(&LIMIT).is_ok());
+        assert!(bucket.take(&LIMIT).is_err());
+        // Never refills past the burst
+        bucket.updated -= Duration::from_secs(600);
+        bucket.refill(&LIMIT);
+        assert_eq!(bucket.tokens, f64::from(LIMIT.burst));
+    }
+
+    #[test]
+    fn finds_most_specific_limit() {
+        let mut limits = limits();
+        let name = |found: Option<(String, &RateLimit)>| found.map(|(name, _)| name);
+        assert_eq!(name(find_limit(&limits, "plugins", Some("files"))).as_deref(), Some("p/files"));
+        assert_eq!(name(find_limit(&limits, "plugins", Some("other"))).as_deref(), Some("plugins"));
+        assert_eq!(name(find_limit(&limits, "auth", None)).as_deref(), Some("global"));
+        limits.global = None;
+        assert!(find_limit(&limits, "auth", None).is_none());
+    }
+
+    #[test]
+    fn new_clients_share_a_bucket_when_full() {
+        let mut buckets = Buckets::new();
+        for i in 0..MAX_BUCKETS {
+            take_token(&mut buckets, "global", format!("ip:{i}"), &LIMIT).unwrap();
+        }
+        // Known clients keep their own bucket
+        assert!(take_token(&mut buckets, "global", "ip:0".into(), &LIMIT).is_ok());
+        assert!(take_token(&mut buckets, "global", "ip:0".into(), &LIMIT).is_err());
+        // New ones are limited together
+        assert!(take_token(&mut buckets, "global", "ip:a".into(), &LIMIT).is_ok());
+        assert!(take_token(&mut buckets, "global", "ip:b".into(), &LIMIT).is_ok());
+        assert!(take_token(&mut buckets, "global", "ip:c".into(), &LIMIT).is_err());
+        assert_eq!(buckets.len(), MAX_BUCKETS + 1);
+    }
+
+    #[test]
+    fn sweep_drops_full_buckets() {
+        let limits = limits();
+        let mut buckets = Buckets::new();
+        take_token(&mut buckets, "global", "ip:used".into(), &LIMIT).unwrap();
+        take_token(&mut buckets, "global", "ip:idle".into(), &LIMIT).unwrap();
+        take_token(&mut buckets, "removed", "ip:idle".into(), &LIMIT).unwrap();
+        buckets.get_mut(&("global".into(), "ip:idle".into())).unwrap().updated -= Duration::from_secs(60);
+        sweep(&mut buckets, &limits);
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets.contains_key(&("global".into(), "ip:used".into())));
+    }
+}
@@ -19,13 +19,23 @@
 //
 // Email: hex0x0000@protonmail.com
 
-use crate::{api, config, error::RequestError, plugins::Plugins, utils, webui};
+use crate::{
+    api, audit, config, database,
+    error::RequestError,
+    listener::{self, Bound, Socket},
+    metrics, middlewares,
+    plugins::{events, Plugins},
+    ratelimit, systemd, utils, webui,
+};
 use actix_identity::IdentityMiddleware;
 use actix_multipart::form::MultipartFormConfig;
 use actix_session::{config::PersistentSession, storage::CookieSessionStore, SessionMiddleware};
 use actix_web::{
-    cookie::{time::Duration, Key, SameSite},
-    error, middleware,
+    cookie::{time::Duration, Key},
+    dev::ServerHandle,
+    error,
+    middleware::{self, from_fn},
+    rt,
     web::{self, Data},
     App, HttpServer,
 };
@@ -39,12 +49,69 @@ fn warn_msg(binding: &str) {
     log::warn!("Any other configuration is *UNSAFE* and may be subject to cyberattacks.");
 }
 
-pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<(), String> {
-    let database = Data::new(database);
+/// Stops the servers gracefully, letting in-flight requests finish
+async fn stop(handles: Vec<ServerHandle>) {
+    log::info!(
+        "Shutting down, waiting up to {}s for requests to finish...",
+        config!(server.shutdown_timeout)
+    );
+    systemd::notify("STOPPING=1");
+    for handle in handles {
+        handle.stop(true).await;
+    }
+}
+
+/// Stops the servers gracefully on SIGINT and SIGTERM
+#[cfg(unix)]
+fn handle_signals(handles: Vec<ServerHandle>) -> Result<(), String> {
+    use rt::signal::unix::{signal, SignalKind};
+
+    for kind in [SignalKind::interrupt(), SignalKind::terminate()] {
+        let mut stream = signal(kind).map_err(|e| format!("Failed to listen for signals: {e}"))?;
+        let handles = handles.clone();
+        rt::spawn(async move {
+            if stream.recv().await.is_some() {
+                stop(handles).await;
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Stops the servers gracefully on Ctrl-C
+#[cfg(not(unix))]
+fn handle_signals(handles: Vec<ServerHandle>) -> Result<(), String> {
+    rt::spawn(async move {
+        match rt::signal::ctrl_c().await {
+            Ok(()) => stop(handles).await,
+            Err(e) => log::error!("Failed to listen for Ctrl-C: {e}"),
+        }
+    });
+    Ok(())
+}
+
+pub async fn start(secret_key: Key, pool: Pool, plugins: Plugins) -> Result<(), String> {
+    let database = Data::new(pool.clone());
     let plugins = Data::new(plugins);
+    let plugins_ref = Data::clone(&plugins);
+    audit::start_retention(pool.clone());
+    events::start(Data::clone(&plugins));
+    let metrics_server = metrics::server(Data::clone(&database))?;
+    if let Some(cors) = config!(cors) {
+        middlewares::check_cors(cors)?;
+    }
+    if let Some(limits) = config!(rate_limit) {
+        ratelimit::check(limits)?;
+        ratelimit::start_sweeper();
+    }
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(middleware::Logger::default())
+            .wrap(from_fn(ratelimit::middleware))
+            .wrap(from_fn(metrics::middleware))
+            .wrap(
+                middleware::Logger::new(r#"%{ip}xi "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{X-Request-Id}i"#)
+                    .custom_request_replace("ip", |req| utils::get_ip(req.request())),
+            )
             .wrap(middleware::NormalizePath::trim())
             .wrap(middleware::Compress::default())
             .app_data(Data::clone(&database))
@@ -76,24 +143,29 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
                     .visit_deadline(config!(duration.visit_minutes).map(|d| std::time::Duration::from_secs(d * 60)))
                     .build(),
             )
-            .wrap({
-                let session_middleware = SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
                     .cookie_name("auth".to_owned())
                     .cookie_http_only(true)
-                    .cookie_same_site(SameSite::Strict)
+                    .cookie_same_site(utils::cookie_same_site())
                     .session_lifecycle(
                         PersistentSession::default().session_ttl(Duration::minutes((*config!(duration.cookie_minutes)).into())),
-                    );
-                #[cfg(feature = "no-tls")]
-                {
-                    session_middleware.cookie_secure(false).build()
-                }
-                #[cfg(not(feature = "no-tls"))]
-                {
-                    session_middleware.cookie_secure(config!(tls).is_some()).build()
+                    )
+                    // Set per request by `middlewares::secure_cookies`
+                    .cookie_secure(false)
+                    .build(),
+            )
+            .wrap(from_fn(middlewares::csrf))
+            .wrap(from_fn(middlewares::secure_cookies))
+            .wrap(from_fn(middlewares::security_headers))
+            .wrap(from_fn(middlewares::access_rules))
+            .wrap(from_fn(middlewares::request_id))
+            .service(web::redirect(utils::make_url(""), utils::make_url("/ui")))
+            .configure(|cfg| {
+                if config!(metrics).as_ref().is_some_and(|m| m.listener.is_none()) {
+                    cfg.route(&utils::make_url("/metrics"), web::get().to(metrics::endpoint));
                 }
             })
-            .service(web::redirect(utils::make_url(""), utils::make_url("/ui")))
             .service(
                 web::scope(&utils::make_url("/static"))
                     .route("/favicon.ico", web::get().to(webui::images::favicon))
@@ -103,13 +175,30 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
                 web::scope(&utils::make_url("/ui"))
                     .service(webui::root)
                     .service(webui::register_page)
-                    .service(webui::login_page),
+                    .service(webui::login_page)
+                    .service(webui::plugin_page)
+                    .service(webui::plugin_icon)
+                    .service(webui::plugin_asset),
             )
             .service(
                 web::scope(&utils::make_url("/api"))
+                    .wrap(from_fn(middlewares::csrf_check))
+                    .wrap(middleware::Condition::new(config!(cors).is_some(), middlewares::cors()))
                     .service(api::info)
+                    .service(api::health::health)
+                    .service(api::health::ready)
+                    .service(api::audit::search)
+                    .service(
+                        web::scope("/admin")
+                            .service(api::admin::get_log_filter)
+                            .service(api::admin::set_log_filter)
+                            .service(api::admin::list_plugins)
+                            .service(api::admin::set_plugin),
+                    )
+                    .service(api::plugins::list)
                     .service(api::plugins::handler)
                     .service(api::plugins::file)
+                    .configure(|cfg| plugins.routes(cfg))
                     .service(
                         web::scope("/auth")
                             .service(api::auth::login)
@@ -126,49 +215,82 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
             )
     });
 
-    // Setting TLS
-    let server = {
-        let binding = format!("{}:{}", config!(server.host), config!(server.port));
-        #[cfg(feature = "openssl")]
-        {
-            use crate::tls;
-            if let Some(config) = config!(tls) {
-                log::info!("Binding to {binding} with TLS (openssl)");
-                server
-                    .bind_openssl(binding, tls::get_openssl_config(config)?)
-                    .map_err(|e| format!("Failed to bind server with TLS (openssl): {e}"))?
-            } else {
-                warn_msg(&binding);
-                server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
-            }
-        }
+    #[cfg(not(feature = "no-tls"))]
+    let server = server.on_connect(crate::tls::on_connect);
+
+    // Binding listeners
+    let mut server = server;
+    for Bound {
+        socket,
+        tls: use_tls,
+        name,
+    } in listener::open(config!(server.listeners))?
+    {
+        server = match socket {
+            #[cfg(not(feature = "no-tls"))]
+            Socket::Tcp(socket) if use_tls => {
+                #[cfg(feature = "openssl")]
+                {
+                    use crate::tls;
+                    log::info!("Binding to {name} with TLS (openssl)");
+                    let config = config!(tls).as_ref().expect("TLS listener without TLS config. This is a bug");
+                    server
+                        .listen_openssl(socket, tls::get_openssl_config(config)?)
+                        .map_err(|e| format!("Failed to bind server with TLS (openssl): {e}"))?
+                }
 
-        #[cfg(feature = "rustls")]
-        {
-            use crate::tls;
-            if let Some(config) = config!(tls) {
-                log::info!("Binding to {binding} with TLS (rustls)");
+                #[cfg(feature = "rustls")]
+                {
+                    use crate::tls;
+                    log::info!("Binding to {name} with TLS (rustls)");
+                    let config = config!(tls).as_ref().expect("TLS listener without TLS config. This is a bug");
+                    server
+                        .listen_rustls_0_23(socket, tls::get_rustls_config(config)?)
+                        .map_err(|e| format!("Failed to bind server with TLS (rustls): {e}"))?
+                }
+            }
+            #[cfg(feature = "no-tls")]
+            Socket::Tcp(_) if use_tls => unreachable!("TLS listener without TLS support. This is a bug"),
+            Socket::Tcp(socket) => {
+                warn_msg(&name);
+                server.listen(socket).map_err(|e| format!("Failed to bind server: {e}"))?
+            }
+            #[cfg(unix)]
+            Socket::Unix(socket) => {
+                log::info!("Binding to {name}");
                 server
-                    .bind_rustls_0_23(binding, tls::get_rustls_config(config)?)
-                    .map_err(|e| format!("Failed to bind server with TLS (rustls): {e}"))?
-            } else {
-                warn_msg(&binding);
-                server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
+                    .listen_uds(socket)
+                    .map_err(|e| format!("Failed to bind server to Unix socket: {e}"))?
             }
-        }
-
-        #[cfg(feature = "no-tls")]
-        {
-            warn_msg(&binding);
-            server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
-        }
-    };
+        };
+    }
 
     log::info!("Starting Tiny Cloud on version {}...", env!("CARGO_PKG_VERSION"),);
-    server
+    let server = server
         .workers(*config!(server.workers))
-        .run()
-        .await
-        .map_err(|e| format!("Error while running: {e}"))?;
+        .shutdown_timeout(*config!(server.shutdown_timeout))
+        .disable_signals()
+        .run();
+    let metrics_handle = metrics_server.as_ref().map(|s| s.handle());
+    handle_signals([Some(server.handle()), metrics_handle.clone()].into_iter().flatten().collect())?;
+    #[cfg(all(feature = "normal-log", unix))]
+    crate::logging::reopen_on_signal()?;
+    let metrics_server = metrics_server.map(rt::spawn);
+    systemd::notify("READY=1");
+    systemd::start_watchdog();
+    server.await.map_err(|e| format!("Error while running: {e}"))?;
+    if let (Some(handle), Some(metrics_server)) = (metrics_handle, metrics_server) {
+        handle.stop(true).await;
+        match metrics_server.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => log::error!("Metrics server error: {e}"),
+            Err(e) => log::error!("Metrics server task failed: {e}"),
+        }
+    }
+
+    events::stop(&plugins_ref).await;
+    plugins_ref.shutdown().await;
+    database::close(pool).await.map_err(|e| format!("Failed to close database: {e}"))?;
+    log::info!("Tiny Cloud stopped.");
     Ok(())
 }
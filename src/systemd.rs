@@ -0,0 +1,83 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use actix_web::rt;
+#[cfg(unix)]
+use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+use std::{env, ffi::OsStr, io, process, time::Duration};
+
+#[cfg(unix)]
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets are not supported")),
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &OsStr, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the service manager is only supported on Unix",
+    ))
+}
+
+/// Sends a state notification (e.g. `READY=1`) to the service manager.
+/// Does nothing if the server was not started by systemd.
+pub fn notify(state: &str) {
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send(&path, state) {
+            log::warn!("Failed to notify `{state}` to the service manager: {e}");
+        }
+    }
+}
+
+/// Starts sending keep-alive pings if the service manager's watchdog is enabled
+pub fn start_watchdog() {
+    let Some(usec) = env::var("WATCHDOG_USEC").ok().and_then(|usec| usec.parse::<u64>().ok()) else {
+        return;
+    };
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return;
+        }
+    }
+    // Pings twice per interval, as recommended by sd_watchdog_enabled(3)
+    let interval = Duration::from_micros(usec / 2);
+    log::info!("Service manager watchdog enabled, pinging every {}ms", interval.as_millis());
+    rt::spawn(async move {
+        let mut timer = rt::time::interval(interval);
+        loop {
+            timer.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
@@ -19,21 +19,45 @@
 //
 // Email: hex0x0000@protonmail.com
 
-use crate::config::Tls;
+use crate::config;
+use crate::config::{ClientAuthMode, Tls};
+use actix_web::{dev::Extensions, HttpRequest};
 #[cfg(feature = "openssl")]
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    rsa::Rsa,
+    ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode},
+    x509::{extension::SubjectAlternativeName, X509Name, X509NameBuilder, X509},
+};
 #[cfg(feature = "rustls")]
-use rustls::{pki_types::CertificateDer, ServerConfig};
+use rustls::{
+    pki_types::CertificateDer,
+    server::{ServerConfig, WebPkiClientVerifier},
+    RootCertStore,
+};
 #[cfg(feature = "rustls")]
 use rustls_pemfile::{certs, private_key};
+use std::any::Any;
+#[cfg(feature = "openssl")]
+use std::net::IpAddr;
 #[cfg(feature = "rustls")]
 use std::{
     fs::File,
     io::{BufReader, Error},
+    sync::Arc,
 };
+use zeroize::Zeroizing;
 
 mutually_exclusive_features::exactly_one_of!("openssl", "rustls");
 
+/// Common name of the subject of a verified client certificate.
+/// Stored in the connection's data.
+pub struct ClientCert(pub String);
+
 #[cfg(feature = "openssl")]
 pub fn get_openssl_config(tls: &Tls) -> Result<SslAcceptorBuilder, String> {
     let mut builder =
@@ -44,13 +68,45 @@ pub fn get_openssl_config(tls: &Tls) -> Result<SslAcceptorBuilder, String> {
     builder
         .set_certificate_chain_file(&tls.cert_path)
         .map_err(|e| format!("Failed to get certificate file: {e}"))?;
+    if let Some(client_auth) = &tls.client_auth {
+        builder
+            .set_ca_file(&client_auth.ca_path)
+            .map_err(|e| format!("Failed to get client CA file: {e}"))?;
+        builder.set_client_ca_list(
+            X509Name::load_client_ca_file(&client_auth.ca_path).map_err(|e| format!("Failed to read client CA file: {e}"))?,
+        );
+        builder.set_verify(match client_auth.mode {
+            ClientAuthMode::Optional => SslVerifyMode::PEER,
+            ClientAuthMode::Required => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        });
+    }
     Ok(builder)
 }
 
 #[cfg(feature = "rustls")]
 pub fn get_rustls_config(tls: &Tls) -> Result<ServerConfig, String> {
     // init server config builder
-    let config = ServerConfig::builder().with_no_client_auth();
+    let config = if let Some(client_auth) = &tls.client_auth {
+        let ca_file = &mut BufReader::new(
+            File::open(&client_auth.ca_path).map_err(|e| format!("Failed to open client CA file at {}: {e}", client_auth.ca_path))?,
+        );
+        let mut roots = RootCertStore::empty();
+        for cert in certs(ca_file) {
+            roots
+                .add(cert.map_err(|e| format!("Failed to read client CA file: {e}"))?)
+                .map_err(|e| format!("Invalid client CA certificate: {e}"))?;
+        }
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+        let verifier = match client_auth.mode {
+            ClientAuthMode::Optional => verifier.allow_unauthenticated(),
+            ClientAuthMode::Required => verifier,
+        }
+        .build()
+        .map_err(|e| format!("Failed to build client certificate verifier: {e}"))?;
+        ServerConfig::builder().with_client_cert_verifier(verifier)
+    } else {
+        ServerConfig::builder().with_no_client_auth()
+    };
 
     // load TLS key/cert files
     let cert_file = &mut BufReader::new(
@@ -68,7 +124,122 @@ pub fn get_rustls_config(tls: &Tls) -> Result<ServerConfig, String> {
         .map_err(|e| format!("Failed to read private key: {e}"))?
         .ok_or("No private key found".to_string())?;
 
-    Ok(config
+    let config = config
         .with_single_cert(cert_chain, key_der)
-        .map_err(|e| format!("Failed to parse certificate and key: {e}"))?)
+        .map_err(|e| format!("Failed to parse certificate and key: {e}"))?;
+    Ok(config)
+}
+
+/// Reads and parses the certificate and key again, to notice when they are removed or replaced by invalid files
+pub fn check(tls: &Tls) -> Result<(), String> {
+    #[cfg(feature = "openssl")]
+    get_openssl_config(tls)?
+        .check_private_key()
+        .map_err(|e| format!("Private key does not match the certificate: {e}"))?;
+    #[cfg(feature = "rustls")]
+    get_rustls_config(tls)?;
+    Ok(())
+}
+
+/// Saves the client certificate's subject in the connection data.
+/// The certificate was already verified during the handshake.
+#[cfg(feature = "openssl")]
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    use actix_tls::accept::openssl::TlsStream;
+    use actix_web::rt::net::TcpStream;
+
+    if let Some(stream) = conn.downcast_ref::<TlsStream<TcpStream>>() {
+        if let Some(cert) = stream.ssl().peer_certificate() {
+            if let Some(name) = cert
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|entry| entry.data().as_utf8().ok())
+            {
+                data.insert(ClientCert(name.to_string()));
+            }
+        }
+    }
+}
+
+/// Saves the client certificate's subject in the connection data.
+/// The certificate was already verified during the handshake.
+#[cfg(feature = "rustls")]
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    use actix_tls::accept::rustls_0_23::TlsStream;
+    use actix_web::rt::net::TcpStream;
+    use x509_parser::parse_x509_certificate;
+
+    if let Some(stream) = conn.downcast_ref::<TlsStream<TcpStream>>() {
+        if let Some(cert) = stream.get_ref().1.peer_certificates().and_then(|certs| certs.first()) {
+            if let Ok((_, cert)) = parse_x509_certificate(cert) {
+                if let Some(name) = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok()) {
+                    data.insert(ClientCert(name.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the user mapped to the client certificate of the request's connection, if any
+pub fn cert_user(req: &HttpRequest) -> Option<&'static String> {
+    let cert = req.conn_data::<ClientCert>()?;
+    config!(tls).as_ref()?.client_auth.as_ref()?.users.get(&cert.0)
+}
+
+/// Generates a self-signed certificate valid for `host` and its private key.
+/// Returns the certificate and the key, both PEM encoded.
+#[cfg(feature = "openssl")]
+pub fn self_signed(host: &str, days: u32) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), String> {
+    let err = |e| format!("Failed to generate self-signed certificate: {e}");
+    let pkey = PKey::from_rsa(Rsa::generate(2048).map_err(err)?).map_err(err)?;
+    let name = {
+        let mut name = X509NameBuilder::new().map_err(err)?;
+        name.append_entry_by_text("CN", host).map_err(err)?;
+        name.build()
+    };
+    let serial = {
+        let mut serial = BigNum::new().map_err(err)?;
+        serial.rand(159, MsbOption::MAYBE_ZERO, false).map_err(err)?;
+        serial.to_asn1_integer().map_err(err)?
+    };
+    let mut builder = X509::builder().map_err(err)?;
+    builder.set_version(2).map_err(err)?;
+    builder.set_serial_number(&serial).map_err(err)?;
+    builder.set_subject_name(&name).map_err(err)?;
+    builder.set_issuer_name(&name).map_err(err)?;
+    builder.set_pubkey(&pkey).map_err(err)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0).map_err(err)?).map_err(err)?;
+    builder.set_not_after(&Asn1Time::days_from_now(days).map_err(err)?).map_err(err)?;
+    let san = {
+        let mut san = SubjectAlternativeName::new();
+        if host.parse::<IpAddr>().is_ok() {
+            san.ip(host);
+        } else {
+            san.dns(host);
+        }
+        san.build(&builder.x509v3_context(None, None)).map_err(err)?
+    };
+    builder.append_extension(san).map_err(err)?;
+    builder.sign(&pkey, MessageDigest::sha256()).map_err(err)?;
+    let cert = builder.build().to_pem().map_err(err)?;
+    let key = Zeroizing::new(pkey.private_key_to_pem_pkcs8().map_err(err)?);
+    Ok((cert, key))
+}
+
+/// Generates a self-signed certificate valid for `host` and its private key.
+/// Returns the certificate and the key, both PEM encoded.
+#[cfg(feature = "rustls")]
+pub fn self_signed(host: &str, days: u32) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), String> {
+    use rcgen::{CertificateParams, KeyPair};
+    use std::time::{Duration, SystemTime};
+
+    let err = |e| format!("Failed to generate self-signed certificate: {e}");
+    let mut params = CertificateParams::new(vec![host.to_string()]).map_err(err)?;
+    let not_after = SystemTime::now() + Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+    params.not_before = SystemTime::now().into();
+    params.not_after = not_after.into();
+    let key_pair = KeyPair::generate().map_err(err)?;
+    let cert = params.self_signed(&key_pair).map_err(err)?;
+    Ok((cert.pem().into_bytes(), Zeroizing::new(key_pair.serialize_pem().into_bytes())))
 }
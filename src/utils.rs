@@ -21,7 +21,12 @@
 
 use crate::config;
 use actix_identity::error::GetIdentityError;
-use actix_web::{dev::ConnectionInfo, HttpResponse};
+use actix_web::{
+    cookie::SameSite,
+    http::header::{self, HeaderMap},
+    HttpRequest, HttpResponse,
+};
+use std::net::{IpAddr, SocketAddr};
 
 /// Creates URL using the prefix specified in settings
 pub fn make_url(url: &str) -> String {
@@ -33,15 +38,166 @@ pub fn make_url(url: &str) -> String {
     }
 }
 
-/// Gets ip of connection's info from the most reliable source
-/// depending on wether or not the server is behind a proxy
-pub fn get_ip(conn: &ConnectionInfo) -> &str {
-    if *config!(server.is_behind_proxy) {
-        conn.realip_remote_addr()
-    } else {
-        conn.peer_addr()
+fn is_trusted(ip: &IpAddr) -> bool {
+    config!(server.trusted_proxies).iter().any(|net| net.contains(ip))
+}
+
+/// Parses a node of the `Forwarded` or `X-Forwarded-For` headers, ignoring its port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(ipv6) = node.strip_prefix('[') {
+        return ipv6.split(']').next()?.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+/// Returns the addresses of the forwarding chain, from the client to the nearest proxy
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = headers
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| parse_node(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Returns whether or not the connection comes from a trusted proxy.
+/// Unix socket connections have no peer address and are always trusted.
+fn from_trusted_proxy(req: &HttpRequest) -> bool {
+    req.peer_addr().map_or(true, |peer| is_trusted(&peer.ip()))
+}
+
+/// Walks the forwarding chain back from the peer and returns the first address that is not a trusted proxy
+fn client_ip(peer: Option<IpAddr>, chain: Vec<Option<IpAddr>>, trusted: impl Fn(&IpAddr) -> bool) -> Option<IpAddr> {
+    if peer.is_some_and(|peer| !trusted(&peer)) {
+        return peer;
+    }
+    let mut ip = peer;
+    for hop in chain.into_iter().rev() {
+        match hop {
+            Some(hop) => {
+                ip = Some(hop);
+                if !trusted(&hop) {
+                    break;
+                }
+            }
+            // Malformed or obfuscated hop, nothing past it can be trusted
+            None => break,
+        }
+    }
+    ip
+}
+
+/// Gets the client's ip, following the forwarding headers only through trusted proxies
+pub fn get_ip(req: &HttpRequest) -> String {
+    let peer = req.peer_addr().map(|peer| peer.ip());
+    if !from_trusted_proxy(req) {
+        return peer.map_or("unknown".into(), |ip| ip.to_string());
+    }
+    client_ip(peer, forwarded_chain(req.headers()), is_trusted).map_or("unknown".into(), |ip| ip.to_string())
+}
+
+/// Returns whether or not the client connected with HTTPS, either directly
+/// or through a trusted proxy that set `X-Forwarded-Proto`
+pub fn is_https(req: &HttpRequest) -> bool {
+    if from_trusted_proxy(req) {
+        if let Some(proto) = req
+            .headers()
+            .get("x-forwarded-proto")
+            .and_then(|proto| proto.to_str().ok())
+            .and_then(|proto| proto.split(',').next())
+        {
+            return proto.trim().eq_ignore_ascii_case("https");
+        }
+    }
+    req.app_config().secure()
+}
+
+/// Returns the `SameSite` attribute of the cookies set by the server
+pub fn cookie_same_site() -> SameSite {
+    match config!(cors).as_ref().map(|cors| &cors.cookie_same_site) {
+        Some(config::SameSite::None) => SameSite::None,
+        Some(config::SameSite::Lax) => SameSite::Lax,
+        Some(config::SameSite::Strict) | None => SameSite::Strict,
+    }
+}
+
+/// Decodes percent-encoded bytes, merges repeated slashes and resolves `.` and `..` segments,
+/// so that a raw request path is classified like the router would match it
+pub fn normalize_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        match hex.and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Returns the route group of a normalized path without the URL prefix
+fn classify(path: &str) -> (&'static str, Option<String>) {
+    let mut segments = path.trim_start_matches('/').split('/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("ui"), ..) => ("ui", None),
+        (Some("static"), ..) => ("static", None),
+        (Some("api"), Some("auth"), _) => ("auth", None),
+        (Some("api"), Some("token"), _) => ("token", None),
+        (Some("api"), Some("admin" | "audit"), _) | (Some("metrics"), ..) => ("admin", None),
+        (Some("api"), Some("p" | "up"), plugin) => ("plugins", plugin.map(String::from)),
+        (Some("api"), ..) => ("api", None),
+        _ => ("global", None),
+    }
+}
+
+/// Returns the route group of a raw request path and, for plugin routes, the plugin's name.
+/// Groups are `ui`, `static`, `auth`, `token`, `admin`, `plugins`, `api` and `global` for the rest.
+pub fn route_group(path: &str) -> (&'static str, Option<String>) {
+    let path = normalize_path(path);
+    let prefix = make_url("");
+    match path.strip_prefix(&prefix) {
+        Some(rest) if !prefix.is_empty() && (rest.is_empty() || rest.starts_with('/')) => classify(rest),
+        _ => classify(&path),
     }
-    .unwrap_or("unknown")
 }
 
 /// Sanitizes a username to make it safe to log or display
@@ -68,3 +224,113 @@ pub fn id_err_into(err: GetIdentityError) -> HttpResponse {
         _ => HttpResponse::Forbidden().body(""),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn trusted(ip: &IpAddr) -> bool {
+        ip.is_loopback() || *ip == self::ip("10.0.0.1")
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"[2001:db8::1]:4711\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn prefers_forwarded_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static("for=192.0.2.1;proto=https, For=10.0.0.1"),
+        );
+        headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_static("198.51.100.1"));
+        assert_eq!(forwarded_chain(&headers), vec![Some(ip("192.0.2.1")), Some(ip("10.0.0.1"))]);
+    }
+
+    #[test]
+    fn falls_back_to_x_forwarded_for() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_static("192.0.2.1, garbage"),
+        );
+        assert_eq!(forwarded_chain(&headers), vec![Some(ip("192.0.2.1")), None]);
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let chain = vec![Some(ip("192.0.2.1"))];
+        assert_eq!(client_ip(Some(ip("203.0.113.9")), chain, trusted), Some(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn walks_through_trusted_proxies() {
+        let chain = vec![Some(ip("192.0.2.1")), Some(ip("10.0.0.1"))];
+        assert_eq!(client_ip(Some(ip("127.0.0.1")), chain, trusted), Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn stops_at_first_untrusted_hop() {
+        // The client can prepend anything, only the hops added by trusted proxies count
+        let chain = vec![Some(ip("192.0.2.1")), Some(ip("198.51.100.7")), Some(ip("10.0.0.1"))];
+        assert_eq!(client_ip(Some(ip("127.0.0.1")), chain, trusted), Some(ip("198.51.100.7")));
+    }
+
+    #[test]
+    fn stops_at_malformed_hop() {
+        let chain = vec![Some(ip("192.0.2.1")), None];
+        assert_eq!(client_ip(Some(ip("127.0.0.1")), chain, trusted), Some(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("/api//admin/log-filter"), "/api/admin/log-filter");
+        assert_eq!(normalize_path("/api/%61dmin/plugins"), "/api/admin/plugins");
+        assert_eq!(normalize_path("/api/./admin"), "/api/admin");
+        assert_eq!(normalize_path("/api/p/../admin/"), "/api/admin");
+        assert_eq!(normalize_path("/../../api"), "/api");
+        // Invalid escapes are kept as they are
+        assert_eq!(normalize_path("/api/%zz/%4"), "/api/%zz/%4");
+        assert_eq!(normalize_path("/api/%+1"), "/api/%+1");
+    }
+
+    #[test]
+    fn classifies_routes() {
+        assert_eq!(classify("/ui/p/files"), ("ui", None));
+        assert_eq!(classify("/api/auth/login"), ("auth", None));
+        assert_eq!(classify("/api/token/new"), ("token", None));
+        assert_eq!(classify("/api/admin/plugins"), ("admin", None));
+        assert_eq!(classify("/api/audit"), ("admin", None));
+        assert_eq!(classify("/metrics"), ("admin", None));
+        assert_eq!(classify("/api/p/files/list"), ("plugins", Some("files".into())));
+        assert_eq!(classify("/api/up/files"), ("plugins", Some("files".into())));
+        assert_eq!(classify("/api/info"), ("api", None));
+        assert_eq!(classify("/"), ("global", None));
+    }
+
+    #[test]
+    fn classifies_obfuscated_routes() {
+        for path in ["/api//admin/log-filter", "/api/%61dmin/plugins", "/api/./admin", "/api/x/../admin"] {
+            assert_eq!(classify(&normalize_path(path)), ("admin", None), "{path}");
+        }
+        assert_eq!(classify(&normalize_path("/api//auth/login")), ("auth", None));
+    }
+
+    #[test]
+    fn trusts_unix_sockets() {
+        let chain = vec![Some(ip("192.0.2.1"))];
+        assert_eq!(client_ip(None, chain, trusted), Some(ip("192.0.2.1")));
+        assert_eq!(client_ip(None, Vec::new(), trusted), None);
+    }
+}
@@ -22,25 +22,109 @@
 mod home;
 pub mod images;
 mod login;
+mod plugin;
 mod register;
 #[macro_use]
 mod macros;
-use crate::{config, utils};
+use crate::{api::plugins::get_user, config, plugins::Plugins, utils};
 use actix_identity::Identity;
-use actix_web::{get, web::Redirect, HttpRequest, HttpResponse, Responder};
+use actix_web::{
+    get,
+    web::{self, Redirect},
+    HttpRequest, HttpResponse, Responder,
+};
+use async_sqlite::Pool;
+use tcloud_library::error::ErrToResponse;
+
+fn login_redirect(req: &HttpRequest) -> HttpResponse {
+    Redirect::to(utils::make_url("/ui/login"))
+        .see_other()
+        .respond_to(req)
+        .map_into_boxed_body()
+}
 
 #[get("")]
-pub async fn root(req: HttpRequest, user: Option<Identity>) -> impl Responder {
-    if let Some(user) = user {
-        match user.id() {
-            Ok(username) => HttpResponse::Ok().body(home::page(username)),
-            Err(e) => utils::id_err_into(e),
+pub async fn root(req: HttpRequest, user: Option<Identity>, pool: web::Data<Pool>, plugins: web::Data<Plugins>) -> impl Responder {
+    if user.is_none() {
+        return login_redirect(&req);
+    }
+    match get_user(&pool, &req, user).await {
+        Ok(Some(user)) => {
+            let username = user.name.clone();
+            let uis = plugins.uis(&Some(user));
+            HttpResponse::Ok().body(home::page(username, &uis))
         }
-    } else {
-        Redirect::to(utils::make_url("/ui/login"))
-            .see_other()
-            .respond_to(&req)
-            .map_into_boxed_body()
+        Ok(None) => login_redirect(&req),
+        Err(resp) => resp,
+    }
+}
+
+#[get("/p/{plugin}")]
+pub async fn plugin_page(
+    req: HttpRequest,
+    user: Option<Identity>,
+    plugin: web::Path<String>,
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+) -> impl Responder {
+    if user.is_none() {
+        return login_redirect(&req);
+    }
+    let user = match get_user(&pool, &req, user).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return login_redirect(&req),
+        Err(resp) => return resp,
+    };
+    let username = user.name.clone();
+    if let Err(err) = plugins.authorize(&plugin, &Some(user)) {
+        return err.to_response();
+    }
+    match plugins.ui(&plugin) {
+        Some(ui) => HttpResponse::Ok().body(plugin::page(username, &plugin, ui)),
+        None => HttpResponse::NotFound().body(""),
+    }
+}
+
+/// Returns an error response unless the user can use `plugin`
+async fn authorize(req: &HttpRequest, user: Option<Identity>, plugin: &str, pool: &Pool, plugins: &Plugins) -> Option<HttpResponse> {
+    match get_user(pool, req, user).await {
+        Ok(user) => plugins.authorize(plugin, &user).err().map(|err| err.to_response()),
+        Err(resp) => Some(resp),
+    }
+}
+
+#[get("/p/{plugin}/icon")]
+pub async fn plugin_icon(
+    req: HttpRequest,
+    user: Option<Identity>,
+    plugin: web::Path<String>,
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+) -> impl Responder {
+    if let Some(resp) = authorize(&req, user, &plugin, &pool, &plugins).await {
+        return resp;
+    }
+    match plugins.ui(&plugin).and_then(|ui| ui.icon.as_ref()) {
+        Some(icon) => HttpResponse::Ok().content_type(icon.content_type).body(icon.data),
+        None => HttpResponse::NotFound().body(""),
+    }
+}
+
+#[get("/p/{plugin}/assets/{name}")]
+pub async fn plugin_asset(
+    req: HttpRequest,
+    user: Option<Identity>,
+    path: web::Path<(String, String)>,
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+) -> impl Responder {
+    let (plugin, name) = path.into_inner();
+    if let Some(resp) = authorize(&req, user, &plugin, &pool, &plugins).await {
+        return resp;
+    }
+    match plugins.ui(&plugin).and_then(|ui| ui.assets.get(name.as_str())) {
+        Some(asset) => HttpResponse::Ok().content_type(asset.content_type).body(asset.data),
+        None => HttpResponse::NotFound().body(""),
     }
 }
 
@@ -19,10 +19,26 @@
 //
 // Email: hex0x0000@protonmail.com
 
-use crate::{config, utils, web_file};
-use maud::{html, PreEscaped, DOCTYPE};
+use crate::{config, plugins::hooks::PluginUi, utils, web_file};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
 
-pub fn page(username: String) -> String {
+/// Links to the web UI of every plugin
+fn launcher(uis: &[(&str, &PluginUi)]) -> Markup {
+    html! {
+        div id="launcher" {
+            @for (name, ui) in uis {
+                a class="plugin" href=(utils::make_url(&format!("/ui/p/{name}"))) {
+                    @if ui.icon.is_some() {
+                        img src=(utils::make_url(&format!("/ui/p/{name}/icon"))) alt="";
+                    }
+                    div { (ui.title) }
+                }
+            }
+        }
+    }
+}
+
+pub fn page(username: String, uis: &[(&str, &PluginUi)]) -> String {
     html! {
         (DOCTYPE)
         html lang="en-US" {
@@ -34,9 +50,11 @@ pub fn page(username: String) -> String {
                 meta name="tcloud-username" content=(username);
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
                 link rel="icon" type="image/x-icon" href=(utils::make_url("/static/favicon.ico"));
+                style { (web_file!("global.css")) (web_file!("home.css")) }
             }
             body {
                 h1 { "Hi " (username) }
+                (launcher(uis))
             }
         }
     }
@@ -0,0 +1,57 @@
+// This file is part of the Tiny Cloud project.
+// You can find the source code of every repository here:
+//		https://github.com/personal-tiny-cloud
+//
+// Copyright (C) 2024  hex0x0000
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Email: hex0x0000@protonmail.com
+
+use crate::{
+    config,
+    plugins::hooks::{PluginUi, UiEntry},
+    utils, web_file,
+};
+use maud::{html, PreEscaped, DOCTYPE};
+
+pub fn page(username: String, name: &str, ui: &PluginUi) -> String {
+    html! {
+        (DOCTYPE)
+        html lang="en-US" {
+            head {
+                title { (ui.title) }
+                meta name="application-name" content=(config!(server_name));
+                meta charset="utf-8";
+                meta name="tcloud-prefix" content=(config!(url_prefix));
+                meta name="tcloud-username" content=(username);
+                meta name="tcloud-plugin" content=(name);
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                link rel="icon" type="image/x-icon" href=(utils::make_url("/static/favicon.ico"));
+                script type="text/javascript" { (web_file!("global.js")) }
+                style { (web_file!("global.css")) }
+                @if let UiEntry::Module(module) = &ui.entry {
+                    script type="module" src=(utils::make_url(&format!("/ui/p/{name}/assets/{module}"))) {}
+                }
+            }
+            body {
+                a href=(utils::make_url("/ui")) { "Home" }
+                @if let UiEntry::Html(fragment) = &ui.entry {
+                    (PreEscaped(fragment))
+                }
+            }
+        }
+    }
+    .into()
+}